@@ -1,165 +1,130 @@
 use std::{
+    fs,
     io::{self, Read},
-    vec,
+    process::ExitCode,
 };
 
-use optimization::{linprog::solve, Consumer, Entity, Grid, Producer, Storage};
-
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-pub struct EntityJson {
-    pub name: String,
-    pub cost_prod: Option<Vec<f64>>,
-    pub power_prod: Option<Vec<f64>>,
-    pub cost_cons: Option<Vec<f64>>,
-    pub power_cons: Option<Vec<f64>>,
-    pub eff_cons: Option<Vec<f64>>,
-    pub eff_prod: Option<Vec<f64>>,
-    pub can_be_disabled: Option<bool>,
-    pub storage_capacity: Option<f64>,
-    pub start_capacity: Option<f64>,
-    pub end_capacity: Option<f64>,
-
-    pub storage_to_grid_allowed: Option<bool>,
-    pub grid_to_storage_allowed: Option<bool>,
-
-    pub consumed: Option<Vec<f64>>,
-    pub produced: Option<Vec<f64>>,
-
-    pub entity_type: String,
+use optimization::{
+    linprog::{diagnose, solve_stochastic, solve_with_options, SolveOptions},
+    rolling::solve_rolling,
+    scenario::Scenario,
+    Error,
+};
+
+/// Settings from the command line.
+struct Flags {
+    options: SolveOptions,
+    /// File the output is written to instead of stdout.
+    output: Option<String>,
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
-pub struct SolverJson {
-    pub entities: Vec<EntityJson>,
-    pub timesteps: usize,
+/// Applies the command-line flags, which take precedence over the input file.
+fn parse_flags(
+    mut options: SolveOptions,
+    args: impl Iterator<Item = String>,
+) -> Result<Flags, Error> {
+    let mut args = args;
+    let mut output = None;
+
+    while let Some(flag) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| Error::Parse(format!("{} is missing a value", flag)))
+        };
+        match flag.as_str() {
+            "--backend" => options.backend = value()?.parse().map_err(Error::Parse)?,
+            "--time-limit" => options.time_limit = Some(parse_number(&flag, value()?)?),
+            "--mip-rel-gap" => options.mip_rel_gap = Some(parse_number(&flag, value()?)?),
+            "--mip-abs-gap" => options.mip_abs_gap = Some(parse_number(&flag, value()?)?),
+            "--threads" => options.threads = Some(parse_number(&flag, value()?)?),
+            "--presolve" => options.presolve = Some(true),
+            "--no-presolve" => options.presolve = Some(false),
+            "--log" => options.log = true,
+            "--prices" => options.prices = true,
+            "--output" => output = Some(value()?),
+            _ => return Err(Error::Parse(format!("Unknown flag {}", flag))),
+        }
+    }
+
+    // The solver prints its log to stdout, where it would mix with the output
+    if options.log && output.is_none() {
+        return Err(Error::Parse("--log requires --output".to_string()));
+    }
+
+    Ok(Flags { options, output })
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: String) -> Result<T, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::Parse(format!("{} must be a number, not {}", flag, value)))
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            println!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
 
-fn main() {
+/// Solves the input read from stdin and writes the result as JSON.
+fn run() -> Result<(), Error> {
     let stdin = io::stdin();
     let mut stdin_lock = stdin.lock();
     let mut input = String::new();
     stdin_lock
         .read_to_string(&mut input)
-        .expect("Failed to read from stdin");
-
-    let solver_json: SolverJson = serde_json::from_str(&input).unwrap();
-
-    let mut entities: Vec<Entity> = vec![];
-
-    for entity in solver_json.entities {
-        match entity.entity_type.as_str() {
-            "Grid" => {
-                let grid = Grid::new(
-                    entity
-                        .cost_cons
-                        .expect(format!("{} is missing cost_cons", entity.name).as_str()),
-                    entity
-                        .power_cons
-                        .expect(format!("{} is missing power_cons", entity.name).as_str()),
-                    entity
-                        .cost_prod
-                        .expect(format!("{} is missing cost_prod", entity.name).as_str()),
-                    entity
-                        .power_prod
-                        .expect(format!("{} is missing power_prod", entity.name).as_str()),
-                    entity.name,
-                );
-                entities.push(Entity::Grid(grid));
-            }
-            "Consumer" => {
-                let consumer = Consumer::new(
-                    entity
-                        .cost_cons
-                        .expect(format!("{} is missing cost_cons", entity.name).as_str()),
-                    entity
-                        .eff_cons
-                        .expect(format!("{} is missing eff_cons", entity.name).as_str()),
-                    entity
-                        .power_cons
-                        .expect(format!("{} is missing power_cons", entity.name).as_str()),
-                    entity.name,
-                );
-                entities.push(Entity::Consumer(consumer));
-            }
-            "Producer" => {
-                let producer = Producer::new(
-                    entity
-                        .cost_prod
-                        .expect(format!("{} is missing cost_prod", entity.name).as_str()),
-                    entity
-                        .eff_prod
-                        .expect(format!("{} is missing eff_prod", entity.name).as_str()),
-                    entity
-                        .power_prod
-                        .expect(format!("{} is missing power_prod", entity.name).as_str()),
-                    entity
-                        .can_be_disabled
-                        .expect(format!("{} is missing can_be_disabled", entity.name).as_str()),
-                    entity.name,
-                );
-                entities.push(Entity::Producer(producer));
-            }
-            "Storage" => {
-
-                if entity.end_capacity.is_some() {
-                    let end_capacity = entity.end_capacity.unwrap();
-                    if end_capacity < 0.0 {
-                        panic!("end_capacity must be greater than or equal to 0.0");
-                    }
-                    if end_capacity > entity.storage_capacity.unwrap() {
-                        panic!("end_capacity must be less than or equal to storage_capacity");
-                    }
-                }
-
-                let storage = Storage::new(
-                    entity
-                        .cost_cons
-                        .expect(format!("{} is missing cost_cons", entity.name).as_str()),
-                    entity
-                        .eff_cons
-                        .expect(format!("{} is missing eff_cons", entity.name).as_str()),
-                    entity
-                        .power_cons
-                        .expect(format!("{} is missing power_cons", entity.name).as_str()),
-                    entity
-                        .cost_prod
-                        .expect(format!("{} is missing cost_prod", entity.name).as_str()),
-                    entity
-                        .eff_prod
-                        .expect(format!("{} is missing eff_prod", entity.name).as_str()),
-                    entity
-                        .power_prod
-                        .expect(format!("{} is missing power_prod", entity.name).as_str()),
-                    entity
-                        .storage_capacity
-                        .expect(format!("{} is missing storage_capacity", entity.name).as_str()),
-                    entity
-                        .start_capacity
-                        .expect(format!("{} is missing start_capacity", entity.name).as_str()),
-                    entity.end_capacity,
-                    entity.storage_to_grid_allowed.unwrap_or(false),
-                    entity.grid_to_storage_allowed.unwrap_or(false),
-                    entity.name,
-                );
-                entities.push(Entity::Storage(storage));
-            }
-            _ => {
-                println!("Unknown entity type: {}", entity.entity_type);
-            }
+        .map_err(|e| Error::Parse(format!("Failed to read from stdin: {}", e)))?;
+
+    let scenario = Scenario::from_json(&input)?;
+    let flags = parse_flags(scenario.options(), std::env::args().skip(1))?;
+
+    let json = solve(&scenario, &flags.options)?;
+    match flags.output {
+        Some(path) => fs::write(&path, json)
+            .map_err(|e| Error::Parse(format!("Failed to write to {}: {}", path, e))),
+        None => {
+            println!("{}", json);
+            Ok(())
         }
     }
+}
 
-    let timesteps: usize = solver_json.timesteps;
+/// Solves the scenario and returns the result as JSON.
+fn solve(scenario: &Scenario, options: &SolveOptions) -> Result<String, Error> {
+    let timesteps = scenario.timesteps;
 
-    let result = solve(entities, timesteps);
+    // Relaxes the input instead of solving it, to find out why it is infeasible
+    if scenario.diagnose {
+        let relaxations = diagnose(&scenario.entities, timesteps, options)?;
+        return Ok(serde_json::to_string(&relaxations)?);
+    }
 
-    match result {
-        Ok(entities) => {
-            let json: String = serde_json::to_string(&entities).unwrap();
-            println!("{}", json);
+    if scenario.scenarios.is_some() {
+        let scenarios = scenario.stochastic_scenarios()?;
+        let result = solve_stochastic(&scenarios, &scenario.first_stage, timesteps, options)?;
+        return Ok(serde_json::to_string(&result)?);
+    }
+
+    // With a look-ahead, the entities are the realized values and the
+    // controller only sees the forecast beyond the committed timesteps
+    let json = match scenario.rolling_horizon() {
+        Some(rolling) => {
+            let forecast = scenario.forecast.as_ref().unwrap_or(&scenario.entities);
+            serde_json::to_string(&solve_rolling(
+                &scenario.entities,
+                forecast,
+                &rolling,
+                options,
+            )?)?
         }
-        Err(e) => {
-            println!("Error: {:?}", e);
+        None => {
+            serde_json::to_string(&solve_with_options(&scenario.entities, timesteps, options)?)?
         }
-    }
+    };
+
+    Ok(json)
 }
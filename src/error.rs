@@ -0,0 +1,67 @@
+use std::fmt;
+
+use good_lp::ResolutionError;
+
+/// Errors of the library and the solver binary.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// A field of an entity or of the options has an invalid value.
+    Invalid {
+        entity: String,
+        field: String,
+        message: String,
+    },
+    /// The input could not be parsed.
+    Parse(String),
+    /// No schedule satisfies all constraints. Holds the cause if it is known.
+    Infeasible(Option<String>),
+    /// The objective can be decreased without limit.
+    Unbounded,
+    /// The solver backend is not available or failed.
+    Solver(String),
+}
+
+impl Error {
+    pub fn invalid(entity: &str, field: &str, message: impl Into<String>) -> Self {
+        Error::Invalid {
+            entity: entity.to_string(),
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Invalid {
+                entity,
+                field,
+                message,
+            } => write!(f, "{} of {} {}", field, entity, message),
+            Error::Parse(message) => write!(f, "Invalid input: {}", message),
+            Error::Infeasible(Some(cause)) => write!(f, "{}", cause),
+            Error::Infeasible(None) => write!(f, "The problem is infeasible"),
+            Error::Unbounded => write!(f, "The problem is unbounded"),
+            Error::Solver(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ResolutionError> for Error {
+    fn from(error: ResolutionError) -> Self {
+        match error {
+            ResolutionError::Infeasible => Error::Infeasible(None),
+            ResolutionError::Unbounded => Error::Unbounded,
+            error => Error::Solver(error.to_string()),
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Parse(error.to_string())
+    }
+}
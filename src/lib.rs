@@ -1,28 +1,152 @@
-use good_lp::Variable;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
+pub mod error;
 pub mod linprog;
+pub mod model;
+pub mod result;
+pub mod rolling;
+pub mod scenario;
+pub mod timeseries;
 
-fn check_eff_vec(eff: &Vec<f64>) {
-    for eff in eff.iter() {
-        if *eff < 0.0 || *eff > 1.0 {
-            panic!("Efficiency must be between 0 and 1");
+pub use error::Error;
+use model::EnergyEntity;
+pub use timeseries::TimeSeries;
+
+/// Capacity that is sized by the solver instead of being given.
+///
+/// `cost` is the annualized investment cost per unit of capacity. With a
+/// `module_size`, only whole modules can be installed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Investment {
+    pub cost: f64,
+    pub min: f64,
+    pub max: f64,
+    pub module_size: Option<f64>,
+}
+
+impl Investment {
+    pub fn new(cost: f64, min: f64, max: f64) -> Result<Self, Error> {
+        let investment = Investment {
+            cost,
+            min,
+            max,
+            module_size: None,
+        };
+        investment.validate("Investment", "bounds")?;
+        Ok(investment)
+    }
+
+    /// Checks the investment of `field` of `entity`.
+    pub fn validate(&self, entity: &str, field: &str) -> Result<(), Error> {
+        if self.min < 0.0 || self.min > self.max {
+            return Err(Error::invalid(
+                entity,
+                field,
+                "must satisfy 0 <= min <= max",
+            ));
+        }
+        if self
+            .module_size
+            .is_some_and(|module_size| module_size <= 0.0)
+        {
+            return Err(Error::invalid(
+                entity,
+                field,
+                "must have a positive module_size",
+            ));
         }
+        Ok(())
+    }
+}
+
+/// Bus used by entities that do not name one explicitly.
+pub const DEFAULT_BUS: &str = "main";
+
+/// Energy carrier of entities that do not name one explicitly.
+pub const DEFAULT_CARRIER: &str = "electricity";
+
+/// Name of the energy balance of `carrier` at `bus`. Electricity keeps the
+/// plain bus name, other carriers are named `bus:carrier`.
+pub fn node_name(bus: &str, carrier: &str) -> String {
+    if carrier == DEFAULT_CARRIER {
+        bus.to_string()
+    } else {
+        format!("{}:{}", bus, carrier)
+    }
+}
+
+fn default_bus() -> String {
+    DEFAULT_BUS.to_string()
+}
+
+fn default_carrier() -> String {
+    DEFAULT_CARRIER.to_string()
+}
+
+fn default_eff() -> TimeSeries {
+    vec![1.0].into()
+}
+
+fn check_eff_vec(entity: &str, field: &str, eff: &TimeSeries) -> Result<(), Error> {
+    check_required(entity, field, eff)?;
+    if eff.values().iter().any(|eff| !(0.0..=1.0).contains(eff)) {
+        return Err(Error::invalid(entity, field, "must be between 0 and 1"));
+    }
+    Ok(())
+}
+
+/// Periodic values are indexed modulo their length, so they must not be empty.
+fn check_not_empty<T>(entity: &str, field: &str, values: &[T]) -> Result<(), Error> {
+    if values.is_empty() {
+        return Err(Error::invalid(entity, field, "must not be empty"));
+    }
+    Ok(())
+}
+
+/// Checks a time series that must have values, unlike the optional ones.
+fn check_required(entity: &str, field: &str, series: &TimeSeries) -> Result<(), Error> {
+    series.validate(entity, field)?;
+    if series.is_empty() {
+        return Err(Error::invalid(entity, field, "must not be empty"));
+    }
+    Ok(())
+}
+
+fn check_non_negative(entity: &str, field: &str, series: &TimeSeries) -> Result<(), Error> {
+    series.validate(entity, field)?;
+    if series.values().iter().any(|value| *value < 0.0) {
+        return Err(Error::invalid(entity, field, "must not be negative"));
     }
+    Ok(())
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Consumer {
-    pub power_cons: Vec<f64>,
-    pub eff_cons: Vec<f64>,
-    pub cost_cons: Vec<f64>,
+    pub power_cons: TimeSeries,
+    pub eff_cons: TimeSeries,
+    pub cost_cons: TimeSeries,
     pub name: String,
+    #[serde(default = "default_bus")]
+    pub bus: String,
+    #[serde(default = "default_carrier")]
+    pub carrier: String,
 
-    #[serde(skip_serializing)]
-    pub consumed_var: Vec<Variable>,
-    pub consumed: Vec<f64>,
+    /// Energy to consume within the window. If set, `power_cons` is the maximum
+    /// power and the solver schedules the load, otherwise the demand is fixed.
+    pub energy: Option<f64>,
+    /// First timestep in which a flexible consumer may consume.
+    #[serde(default)]
+    pub earliest_start: usize,
+    /// Timestep by which a flexible consumer must have finished, the end of the
+    /// horizon if `None`.
+    pub latest_end: Option<usize>,
+    /// Least energy to consume within the horizon if the window continues
+    /// after it, e.g. in a solve of a rolling horizon. `energy` is then the
+    /// most the consumer may take. The whole `energy` is needed if `None`.
+    pub min_energy: Option<f64>,
 
-    pub entity_type: String,
+    /// Penalty per unit of unserved energy. Without it, the demand must be met.
+    pub value_of_lost_load: Option<f64>,
 }
 
 impl Consumer {
@@ -32,32 +156,53 @@ impl Consumer {
         power_cons: Vec<f64>,
 
         name: String,
-    ) -> Self {
-        check_eff_vec(&eff_cons);
-        Consumer {
-            power_cons,
-            eff_cons,
+    ) -> Result<Self, Error> {
+        let consumer = Consumer {
+            power_cons: power_cons.into(),
+            eff_cons: eff_cons.into(),
             name,
-            cost_cons,
-            consumed_var: vec![],
-            consumed: vec![],
-            entity_type: "Consumer".to_string(),
+            bus: DEFAULT_BUS.to_string(),
+            carrier: DEFAULT_CARRIER.to_string(),
+            cost_cons: cost_cons.into(),
+            energy: None,
+            earliest_start: 0,
+            latest_end: None,
+            min_energy: None,
+            value_of_lost_load: None,
+        };
+        consumer.validate()?;
+        Ok(consumer)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        check_required(&self.name, "power_cons", &self.power_cons)?;
+        check_eff_vec(&self.name, "eff_cons", &self.eff_cons)?;
+        check_required(&self.name, "cost_cons", &self.cost_cons)?;
+        if self.energy.is_some_and(|energy| energy < 0.0) {
+            return Err(Error::invalid(&self.name, "energy", "must not be negative"));
+        }
+        if let Some(min_energy) = self.min_energy {
+            if !(0.0..=self.energy.unwrap_or(0.0)).contains(&min_energy) {
+                return Err(Error::invalid(
+                    &self.name,
+                    "min_energy",
+                    "must be between 0 and energy",
+                ));
+            }
         }
+        Ok(())
     }
 
     pub fn get_power_cons(&self, timestep: usize) -> f64 {
-        let index = timestep % self.power_cons.len();
-        self.power_cons[index]
+        self.power_cons.get(timestep)
     }
 
     pub fn get_eff_cons(&self, timestep: usize) -> f64 {
-        let index = timestep % self.eff_cons.len();
-        self.eff_cons[index]
+        self.eff_cons.get(timestep)
     }
 
     pub fn get_cost_cons(&self, timestep: usize) -> f64 {
-        let index = timestep % self.cost_cons.len();
-        self.cost_cons[index]
+        self.cost_cons.get(timestep)
     }
 }
 
@@ -66,27 +211,72 @@ impl Serialize for Entity {
     where
         S: serde::Serializer,
     {
-        match self {
-            Entity::Consumer(consumer) => consumer.serialize(serializer),
-            Entity::Producer(producer) => producer.serialize(serializer),
-            Entity::Storage(storage) => storage.serialize(serializer),
-            Entity::Grid(grid) => grid.serialize(serializer),
-        }
+        let tagged = match self {
+            Entity::Consumer(consumer) => TaggedEntityRef::Consumer(consumer),
+            Entity::Producer(producer) => TaggedEntityRef::Producer(producer),
+            Entity::Storage(storage) => TaggedEntityRef::Storage(storage),
+            Entity::Grid(grid) => TaggedEntityRef::Grid(grid),
+            Entity::Line(line) => TaggedEntityRef::Line(line),
+            Entity::ConnectionPoint(connection_point) => {
+                TaggedEntityRef::ConnectionPoint(connection_point)
+            }
+            Entity::ElectricVehicle(electric_vehicle) => {
+                TaggedEntityRef::ElectricVehicle(electric_vehicle)
+            }
+            Entity::Converter(converter) => TaggedEntityRef::Converter(converter),
+            Entity::Custom(entity) => {
+                TaggedEntityRef::Custom(entity.to_json().map_err(serde::ser::Error::custom)?)
+            }
+        };
+        tagged.serialize(serializer)
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Producer {
-    pub entity_type: String,
     pub can_be_disabled: bool,
-    pub power_prod: Vec<f64>,
-    pub eff_prod: Vec<f64>,
-    pub cost_prod: Vec<f64>,
+    pub power_prod: TimeSeries,
+    pub eff_prod: TimeSeries,
+    pub cost_prod: TimeSeries,
     pub name: String,
-    #[serde(skip_serializing)]
-    pub produced_var: Vec<Variable>,
+    #[serde(default = "default_bus")]
+    pub bus: String,
+    #[serde(default = "default_carrier")]
+    pub carrier: String,
+
+    /// Minimum output while on, as a fraction of `power_prod`.
+    #[serde(default)]
+    pub min_load: f64,
+    #[serde(default)]
+    pub startup_cost: f64,
+    #[serde(default)]
+    pub shutdown_cost: f64,
+    /// Minimum number of timesteps the producer stays on after a start.
+    #[serde(default)]
+    pub min_up_time: usize,
+    /// Minimum number of timesteps the producer stays off after a stop.
+    #[serde(default)]
+    pub min_down_time: usize,
+    /// Whether the producer is on before the first timestep.
+    #[serde(default)]
+    pub initially_on: bool,
+    /// Output before the first timestep, which the ramp limits of the first
+    /// timestep refer to. The first timestep is unlimited if `None`.
+    pub initial_output: Option<f64>,
+
+    /// Ramp limits on `produced`, unlimited if empty.
+    #[serde(default)]
+    pub ramp_up: TimeSeries,
+    #[serde(default)]
+    pub ramp_down: TimeSeries,
 
-    pub produced: Vec<f64>,
+    /// Emissions per unit of produced energy, no emissions if empty.
+    #[serde(default)]
+    pub emission_factor: TimeSeries,
+
+    /// Sizes the producer. `power_prod` is then the output per unit of
+    /// installed capacity.
+    pub invest_power: Option<Investment>,
 }
 
 impl Producer {
@@ -98,71 +288,151 @@ impl Producer {
         can_be_disabled: bool,
 
         name: String,
-    ) -> Self {
-        check_eff_vec(&eff_prod);
-        Producer {
-            power_prod,
-            eff_prod,
-            cost_prod,
+    ) -> Result<Self, Error> {
+        let producer = Producer {
+            power_prod: power_prod.into(),
+            eff_prod: eff_prod.into(),
+            cost_prod: cost_prod.into(),
             name,
+            bus: DEFAULT_BUS.to_string(),
+            carrier: DEFAULT_CARRIER.to_string(),
             can_be_disabled,
-            produced_var: vec![],
-            produced: vec![],
-            entity_type: "Producer".to_string(),
+            min_load: 0.0,
+            startup_cost: 0.0,
+            shutdown_cost: 0.0,
+            min_up_time: 0,
+            min_down_time: 0,
+            initially_on: false,
+            initial_output: None,
+            ramp_up: TimeSeries::default(),
+            ramp_down: TimeSeries::default(),
+            emission_factor: TimeSeries::default(),
+            invest_power: None,
+        };
+        producer.validate()?;
+        Ok(producer)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        check_required(&self.name, "power_prod", &self.power_prod)?;
+        check_eff_vec(&self.name, "eff_prod", &self.eff_prod)?;
+        check_required(&self.name, "cost_prod", &self.cost_prod)?;
+        self.ramp_up.validate(&self.name, "ramp_up")?;
+        self.ramp_down.validate(&self.name, "ramp_down")?;
+        self.emission_factor
+            .validate(&self.name, "emission_factor")?;
+        if !(0.0..=1.0).contains(&self.min_load) {
+            return Err(Error::invalid(
+                &self.name,
+                "min_load",
+                "must be between 0 and 1",
+            ));
+        }
+        if let Some(invest_power) = &self.invest_power {
+            if self.has_unit_commitment() {
+                return Err(Error::invalid(
+                    &self.name,
+                    "invest_power",
+                    "cannot be combined with unit commitment",
+                ));
+            }
+            invest_power.validate(&self.name, "invest_power")?;
         }
+        Ok(())
     }
 
     pub fn get_power_prod(&self, timestep: usize) -> f64 {
-        let index = timestep % self.power_prod.len();
-        self.power_prod[index]
+        self.power_prod.get(timestep)
     }
 
     pub fn get_eff_prod(&self, timestep: usize) -> f64 {
-        let index = timestep % self.eff_prod.len();
-        self.eff_prod[index]
+        self.eff_prod.get(timestep)
     }
 
     pub fn get_cost_prod(&self, timestep: usize) -> f64 {
-        let index = timestep % self.cost_prod.len();
-        self.cost_prod[index]
+        self.cost_prod.get(timestep)
+    }
+
+    pub fn get_emission_factor(&self, timestep: usize) -> f64 {
+        if self.emission_factor.is_empty() {
+            return 0.0;
+        }
+        self.emission_factor.get(timestep)
+    }
+
+    /// Maximum increase of the output compared to the previous timestep.
+    pub fn get_ramp_up(&self, timestep: usize) -> Option<f64> {
+        if self.ramp_up.is_empty() {
+            return None;
+        }
+        Some(self.ramp_up.get(timestep))
+    }
+
+    /// Maximum decrease of the output compared to the previous timestep.
+    pub fn get_ramp_down(&self, timestep: usize) -> Option<f64> {
+        if self.ramp_down.is_empty() {
+            return None;
+        }
+        Some(self.ramp_down.get(timestep))
+    }
+
+    /// Whether the producer needs on/off decisions, i.e. any unit commitment
+    /// parameter is set.
+    pub fn has_unit_commitment(&self) -> bool {
+        self.min_load > 0.0
+            || self.startup_cost != 0.0
+            || self.shutdown_cost != 0.0
+            || self.min_up_time > 1
+            || self.min_down_time > 1
     }
 }
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Storage {
-    pub power_prod: Vec<f64>,
-    pub eff_prod: Vec<f64>,
-    pub cost_prod: Vec<f64>,
+    pub power_prod: TimeSeries,
+    pub eff_prod: TimeSeries,
+    pub cost_prod: TimeSeries,
 
-    pub power_cons: Vec<f64>,
-    pub eff_cons: Vec<f64>,
-    pub cost_cons: Vec<f64>,
+    pub power_cons: TimeSeries,
+    pub eff_cons: TimeSeries,
+    pub cost_cons: TimeSeries,
 
+    /// Read as the `max` of `invest_capacity` if it is missing.
+    #[serde(default)]
     pub storage_capacity: f64,
     pub start_capacity: f64,
+    #[serde(default)]
     pub storage_to_grid_allowed: bool,
+    #[serde(default)]
     pub grid_to_storage_allowed: bool,
 
     pub name: String,
+    #[serde(default = "default_bus")]
+    pub bus: String,
+    #[serde(default = "default_carrier")]
+    pub carrier: String,
 
-    #[serde(skip_serializing)]
-    pub end_capacity: Option<f64>,
-
-    #[serde(skip_serializing)]
-    pub produced_var: Vec<Variable>,
-    #[serde(skip_serializing)]
-    pub consumed_var: Vec<Variable>,
+    /// Share of the stored energy lost per hour, no losses if empty.
+    #[serde(default)]
+    pub self_discharge: TimeSeries,
 
-    #[serde(skip_serializing)]
-    pub producing_var: Vec<Variable>,
+    /// Sizes `storage_capacity`, which is then ignored.
+    pub invest_capacity: Option<Investment>,
 
-    pub produced: Vec<f64>,
-    pub consumed: Vec<f64>,
-    pub stored: Vec<f64>,
+    /// Ramp limits on `produced - consumed`, unlimited if empty.
+    #[serde(default)]
+    pub ramp_up: TimeSeries,
+    #[serde(default)]
+    pub ramp_down: TimeSeries,
+    /// `produced - consumed` before the first timestep, which the ramp limits
+    /// of the first timestep refer to. The first timestep is unlimited if `None`.
+    pub initial_output: Option<f64>,
 
-    pub entity_type: String,
+    /// Stored energy required at the end of the horizon.
+    pub end_capacity: Option<f64>,
 }
 
 impl Storage {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         cost_cons: Vec<f64>,
         eff_cons: Vec<f64>,
@@ -180,26 +450,15 @@ impl Storage {
         grid_to_storage_allowed: bool,
 
         name: String,
-    ) -> Self {
-        check_eff_vec(&eff_prod);
-        check_eff_vec(&eff_cons);
+    ) -> Result<Self, Error> {
+        let storage = Storage {
+            cost_cons: cost_cons.into(),
+            eff_cons: eff_cons.into(),
+            power_cons: power_cons.into(),
 
-        if start_capacity < 0.0 || start_capacity > storage_capacity {
-            panic!("start_capacity must be between 0 and storage_capacity");
-        }
-
-        if storage_capacity <= 0.0 {
-            panic!("storage_capacity must be greater than 0");
-        }
-
-        Storage {
-            cost_cons,
-            eff_cons,
-            power_cons,
-
-            cost_prod,
-            eff_prod,
-            power_prod,
+            cost_prod: cost_prod.into(),
+            eff_prod: eff_prod.into(),
+            power_prod: power_prod.into(),
 
             storage_capacity,
             start_capacity,
@@ -208,71 +467,161 @@ impl Storage {
             grid_to_storage_allowed,
 
             name,
+            bus: DEFAULT_BUS.to_string(),
+            carrier: DEFAULT_CARRIER.to_string(),
+
+            self_discharge: TimeSeries::default(),
+            invest_capacity: None,
+
+            ramp_up: TimeSeries::default(),
+            ramp_down: TimeSeries::default(),
+            initial_output: None,
+        };
+        storage.validate()?;
+        Ok(storage)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        check_required(&self.name, "power_prod", &self.power_prod)?;
+        check_eff_vec(&self.name, "eff_prod", &self.eff_prod)?;
+        check_required(&self.name, "cost_prod", &self.cost_prod)?;
+        check_required(&self.name, "power_cons", &self.power_cons)?;
+        check_eff_vec(&self.name, "eff_cons", &self.eff_cons)?;
+        check_required(&self.name, "cost_cons", &self.cost_cons)?;
+        self.ramp_up.validate(&self.name, "ramp_up")?;
+        self.ramp_down.validate(&self.name, "ramp_down")?;
 
-            produced_var: vec![],
-            consumed_var: vec![],
-            producing_var: vec![],
-            produced: vec![],
-            consumed: vec![],
-            stored: vec![],
-            entity_type: "Storage".to_string(),
+        if self.storage_capacity <= 0.0 {
+            return Err(Error::invalid(
+                &self.name,
+                "storage_capacity",
+                "must be greater than 0",
+            ));
         }
+        if self.start_capacity < 0.0 || self.start_capacity > self.storage_capacity {
+            return Err(Error::invalid(
+                &self.name,
+                "start_capacity",
+                "must be between 0 and storage_capacity",
+            ));
+        }
+        if self
+            .end_capacity
+            .is_some_and(|end_capacity| end_capacity < 0.0 || end_capacity > self.storage_capacity)
+        {
+            return Err(Error::invalid(
+                &self.name,
+                "end_capacity",
+                "must be between 0 and storage_capacity",
+            ));
+        }
+        self.self_discharge.validate(&self.name, "self_discharge")?;
+        if self
+            .self_discharge
+            .values()
+            .iter()
+            .any(|self_discharge| !(0.0..=1.0).contains(self_discharge))
+        {
+            return Err(Error::invalid(
+                &self.name,
+                "self_discharge",
+                "must be between 0 and 1",
+            ));
+        }
+        if let Some(invest_capacity) = &self.invest_capacity {
+            invest_capacity.validate(&self.name, "invest_capacity")?;
+        }
+        Ok(())
     }
 
     pub fn get_power_prod(&self, timestep: usize) -> f64 {
-        let index = timestep % self.power_prod.len();
-        self.power_prod[index]
+        self.power_prod.get(timestep)
     }
 
     pub fn get_eff_prod(&self, timestep: usize) -> f64 {
-        let index = timestep % self.eff_prod.len();
-        self.eff_prod[index]
+        self.eff_prod.get(timestep)
     }
 
     pub fn get_cost_prod(&self, timestep: usize) -> f64 {
-        let index = timestep % self.cost_prod.len();
-        self.cost_prod[index]
+        self.cost_prod.get(timestep)
     }
 
     pub fn get_power_cons(&self, timestep: usize) -> f64 {
-        let index = timestep % self.power_cons.len();
-        self.power_cons[index]
+        self.power_cons.get(timestep)
     }
 
     pub fn get_eff_cons(&self, timestep: usize) -> f64 {
-        let index = timestep % self.eff_cons.len();
-        self.eff_cons[index]
+        self.eff_cons.get(timestep)
     }
 
     pub fn get_cost_cons(&self, timestep: usize) -> f64 {
-        let index = timestep % self.cost_cons.len();
-        self.cost_cons[index]
+        self.cost_cons.get(timestep)
+    }
+
+    pub fn get_self_discharge(&self, timestep: usize) -> f64 {
+        if self.self_discharge.is_empty() {
+            return 0.0;
+        }
+        self.self_discharge.get(timestep)
+    }
+
+    /// Maximum increase of the output compared to the previous timestep.
+    pub fn get_ramp_up(&self, timestep: usize) -> Option<f64> {
+        if self.ramp_up.is_empty() {
+            return None;
+        }
+        Some(self.ramp_up.get(timestep))
+    }
+
+    /// Maximum decrease of the output compared to the previous timestep.
+    pub fn get_ramp_down(&self, timestep: usize) -> Option<f64> {
+        if self.ramp_down.is_empty() {
+            return None;
+        }
+        Some(self.ramp_down.get(timestep))
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Grid {
     pub name: String,
+    #[serde(default = "default_bus")]
+    pub bus: String,
+    #[serde(default = "default_carrier")]
+    pub carrier: String,
 
-    pub power_prod: Vec<f64>,
-    pub cost_prod: Vec<f64>,
-
-    pub power_cons: Vec<f64>,
-    pub cost_cons: Vec<f64>,
+    pub power_prod: TimeSeries,
+    pub cost_prod: TimeSeries,
 
+    pub power_cons: TimeSeries,
+    pub cost_cons: TimeSeries,
 
-    #[serde(skip_serializing)]
-    pub produced_var: Vec<Variable>,
-    #[serde(skip_serializing)]
-    pub consumed_var: Vec<Variable>,
+    /// Ramp limits on `produced - consumed`, unlimited if empty.
+    #[serde(default)]
+    pub ramp_up: TimeSeries,
+    #[serde(default)]
+    pub ramp_down: TimeSeries,
+    /// `produced - consumed` before the first timestep, which the ramp limits
+    /// of the first timestep refer to. The first timestep is unlimited if `None`.
+    pub initial_output: Option<f64>,
 
-    #[serde(skip_serializing)]
-    pub producing_var: Vec<Variable>,
+    /// Demand charge per unit of the maximum import within a billing period.
+    #[serde(default)]
+    pub peak_cost: f64,
+    /// Length of a billing period in timesteps, the whole horizon if `None`.
+    pub peak_window: Option<usize>,
+    /// Timesteps of the first billing period before the first timestep, e.g.
+    /// the timesteps committed by earlier solves of a rolling horizon.
+    #[serde(default)]
+    pub peak_offset: usize,
+    /// Import already reached in those timesteps. The peak of the first
+    /// billing period is at least this.
+    #[serde(default)]
+    pub initial_peak: f64,
 
-    pub produced: Vec<f64>,
-    pub consumed: Vec<f64>,
-
-    pub entity_type: String,
+    /// Emissions per unit of imported energy, no emissions if empty.
+    #[serde(default)]
+    pub emission_factor: TimeSeries,
 }
 
 impl Grid {
@@ -284,48 +633,734 @@ impl Grid {
         power_prod: Vec<f64>,
 
         name: String,
-    ) -> Self {
-        Grid {
+    ) -> Result<Self, Error> {
+        let grid = Grid {
             name,
-            cost_prod,
-            power_prod,
-            cost_cons,
-            power_cons,
+            bus: DEFAULT_BUS.to_string(),
+            carrier: DEFAULT_CARRIER.to_string(),
+            cost_prod: cost_prod.into(),
+            power_prod: power_prod.into(),
+            cost_cons: cost_cons.into(),
+            power_cons: power_cons.into(),
+
+            ramp_up: TimeSeries::default(),
+            ramp_down: TimeSeries::default(),
+            initial_output: None,
 
-            produced_var: vec![],
-            consumed_var: vec![],
-            producing_var: vec![],
-            produced: vec![],
-            consumed: vec![],
-            entity_type: "Grid".to_string(),
+            peak_cost: 0.0,
+            peak_window: None,
+            peak_offset: 0,
+            initial_peak: 0.0,
+
+            emission_factor: TimeSeries::default(),
+        };
+        grid.validate()?;
+        Ok(grid)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        check_required(&self.name, "power_prod", &self.power_prod)?;
+        check_required(&self.name, "cost_prod", &self.cost_prod)?;
+        check_required(&self.name, "power_cons", &self.power_cons)?;
+        check_required(&self.name, "cost_cons", &self.cost_cons)?;
+        self.ramp_up.validate(&self.name, "ramp_up")?;
+        self.ramp_down.validate(&self.name, "ramp_down")?;
+        self.emission_factor
+            .validate(&self.name, "emission_factor")?;
+        if self.peak_cost < 0.0 {
+            return Err(Error::invalid(
+                &self.name,
+                "peak_cost",
+                "must not be negative",
+            ));
+        }
+        if self.peak_window == Some(0) {
+            return Err(Error::invalid(
+                &self.name,
+                "peak_window",
+                "must be positive",
+            ));
+        }
+        if self.initial_peak < 0.0 {
+            return Err(Error::invalid(
+                &self.name,
+                "initial_peak",
+                "must not be negative",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Billing period of a timestep, counted from the period of the first
+    /// timestep.
+    pub fn get_peak_period(&self, timestep: usize) -> usize {
+        match self.peak_window {
+            Some(peak_window) => {
+                (timestep + self.peak_offset) / peak_window - self.peak_offset / peak_window
+            }
+            None => 0,
+        }
+    }
+
+    /// First timestep of a billing period.
+    pub fn get_peak_period_start(&self, period: usize) -> usize {
+        match self.peak_window {
+            Some(peak_window) if period > 0 => {
+                period * peak_window - self.peak_offset % peak_window
+            }
+            _ => 0,
+        }
+    }
+
+    /// Maximum of `produced` per billing period, including the `initial_peak`.
+    pub fn peaks(&self, produced: &[f64]) -> Vec<f64> {
+        let mut peaks: Vec<f64> = vec![];
+        if !produced.is_empty() {
+            peaks.push(self.initial_peak);
+        }
+        for (i, produced) in produced.iter().enumerate() {
+            let period = self.get_peak_period(i);
+            if period == peaks.len() {
+                peaks.push(0.0);
+            }
+            peaks[period] = peaks[period].max(*produced);
         }
+        peaks
     }
 
     pub fn get_cost_prod(&self, timestep: usize) -> f64 {
-        let index = timestep % self.cost_prod.len();
-        self.cost_prod[index]
+        self.cost_prod.get(timestep)
     }
 
     pub fn get_cost_cons(&self, timestep: usize) -> f64 {
-        let index = timestep % self.cost_cons.len();
-        self.cost_cons[index]
+        self.cost_cons.get(timestep)
     }
 
     pub fn get_power_prod(&self, timestep: usize) -> f64 {
-        let index = timestep % self.power_prod.len();
-        self.power_prod[index]
+        self.power_prod.get(timestep)
     }
 
     pub fn get_power_cons(&self, timestep: usize) -> f64 {
-        let index = timestep % self.power_cons.len();
-        self.power_cons[index]
+        self.power_cons.get(timestep)
     }
+
+    pub fn get_emission_factor(&self, timestep: usize) -> f64 {
+        if self.emission_factor.is_empty() {
+            return 0.0;
+        }
+        self.emission_factor.get(timestep)
+    }
+
+    /// Maximum increase of the output compared to the previous timestep.
+    pub fn get_ramp_up(&self, timestep: usize) -> Option<f64> {
+        if self.ramp_up.is_empty() {
+            return None;
+        }
+        Some(self.ramp_up.get(timestep))
+    }
+
+    /// Maximum decrease of the output compared to the previous timestep.
+    pub fn get_ramp_down(&self, timestep: usize) -> Option<f64> {
+        if self.ramp_down.is_empty() {
+            return None;
+        }
+        Some(self.ramp_down.get(timestep))
+    }
+}
+
+/// Electric vehicle that can only charge while it is plugged in.
+///
+/// While `available` is false, the vehicle is away and its battery is drained by
+/// `consumption`. Whenever it leaves, the stored energy must reach
+/// `departure_soc`. A vehicle with `power_discharge` can feed back into the bus
+/// (vehicle-to-grid).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ElectricVehicle {
+    pub name: String,
+    #[serde(default = "default_bus")]
+    pub bus: String,
+
+    pub power_charge: TimeSeries,
+    pub eff_charge: TimeSeries,
+    /// Vehicle-to-grid capacity, no discharge if empty.
+    #[serde(default)]
+    pub power_discharge: TimeSeries,
+    #[serde(default = "default_eff")]
+    pub eff_discharge: TimeSeries,
+
+    pub battery_capacity: f64,
+    pub start_capacity: f64,
+
+    /// Whether the vehicle is plugged in per timestep.
+    pub available: Vec<bool>,
+    /// Whether the vehicle was plugged in before the first timestep, so that
+    /// it departs in the first timestep if it is away then.
+    #[serde(default)]
+    pub initially_available: bool,
+    /// Power used for driving while the vehicle is away.
+    #[serde(default)]
+    pub consumption: TimeSeries,
+    /// Stored energy required when the vehicle leaves.
+    pub departure_soc: TimeSeries,
+    /// Penalty per unit of missing energy at a departure. Without it, the
+    /// departure targets must be met.
+    pub shortfall_cost: Option<f64>,
 }
 
-#[derive(Debug)]
+impl ElectricVehicle {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        power_charge: Vec<f64>,
+        eff_charge: Vec<f64>,
+
+        battery_capacity: f64,
+        start_capacity: f64,
+
+        available: Vec<bool>,
+        consumption: Vec<f64>,
+        departure_soc: Vec<f64>,
+
+        name: String,
+    ) -> Result<Self, Error> {
+        let electric_vehicle = ElectricVehicle {
+            name,
+            bus: DEFAULT_BUS.to_string(),
+            power_charge: power_charge.into(),
+            eff_charge: eff_charge.into(),
+            power_discharge: TimeSeries::default(),
+            eff_discharge: vec![1.0].into(),
+            battery_capacity,
+            start_capacity,
+            available,
+            initially_available: false,
+            consumption: consumption.into(),
+            departure_soc: departure_soc.into(),
+            shortfall_cost: None,
+        };
+        electric_vehicle.validate()?;
+        Ok(electric_vehicle)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        check_required(&self.name, "power_charge", &self.power_charge)?;
+        check_eff_vec(&self.name, "eff_charge", &self.eff_charge)?;
+        self.power_discharge
+            .validate(&self.name, "power_discharge")?;
+        check_eff_vec(&self.name, "eff_discharge", &self.eff_discharge)?;
+        check_not_empty(&self.name, "available", &self.available)?;
+        self.consumption.validate(&self.name, "consumption")?;
+        check_required(&self.name, "departure_soc", &self.departure_soc)?;
+
+        if self.start_capacity < 0.0 || self.start_capacity > self.battery_capacity {
+            return Err(Error::invalid(
+                &self.name,
+                "start_capacity",
+                "must be between 0 and battery_capacity",
+            ));
+        }
+        if self
+            .departure_soc
+            .values()
+            .iter()
+            .any(|soc| *soc > self.battery_capacity)
+        {
+            return Err(Error::invalid(
+                &self.name,
+                "departure_soc",
+                "must not exceed battery_capacity",
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn get_power_charge(&self, timestep: usize) -> f64 {
+        self.power_charge.get(timestep)
+    }
+
+    pub fn get_eff_charge(&self, timestep: usize) -> f64 {
+        self.eff_charge.get(timestep)
+    }
+
+    pub fn get_power_discharge(&self, timestep: usize) -> f64 {
+        if self.power_discharge.is_empty() {
+            return 0.0;
+        }
+        self.power_discharge.get(timestep)
+    }
+
+    pub fn get_eff_discharge(&self, timestep: usize) -> f64 {
+        self.eff_discharge.get(timestep)
+    }
+
+    pub fn is_available(&self, timestep: usize) -> bool {
+        let index = timestep % self.available.len();
+        self.available[index]
+    }
+
+    pub fn get_consumption(&self, timestep: usize) -> f64 {
+        if self.consumption.is_empty() {
+            return 0.0;
+        }
+        self.consumption.get(timestep)
+    }
+
+    pub fn get_departure_soc(&self, timestep: usize) -> f64 {
+        self.departure_soc.get(timestep)
+    }
+
+    /// Whether the vehicle leaves at the start of `timestep`.
+    pub fn is_departure(&self, timestep: usize) -> bool {
+        let was_available = match timestep {
+            0 => self.initially_available,
+            _ => self.is_available(timestep - 1),
+        };
+        was_available && !self.is_available(timestep)
+    }
+}
+
+/// Input or output of a converter. Its flow is the activity of the converter
+/// times `factor`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConverterPort {
+    pub carrier: String,
+    pub factor: TimeSeries,
+}
+
+impl ConverterPort {
+    pub fn new(carrier: String, factor: Vec<f64>) -> Result<Self, Error> {
+        let port = ConverterPort {
+            carrier,
+            factor: factor.into(),
+        };
+        port.validate("ConverterPort")?;
+        Ok(port)
+    }
+
+    /// Checks the factors of the port of converter `entity`.
+    pub fn validate(&self, entity: &str) -> Result<(), Error> {
+        let field = format!("factor of {}", self.carrier);
+        check_required(entity, &field, &self.factor)?;
+        check_non_negative(entity, &field, &self.factor)
+    }
+
+    pub fn get_factor(&self, timestep: usize) -> f64 {
+        self.factor.get(timestep)
+    }
+}
+
+/// Couples energy carriers at its bus, e.g. a heat pump, a CHP, an
+/// electrolyser or a fuel cell.
+///
+/// The activity of the converter is limited by `power`. Every input and output
+/// flows in proportion to the activity, so a heat pump has an electricity input
+/// with factor 1 and a heat output with its COP as factor. `cost` is charged per
+/// unit of activity.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Converter {
+    pub name: String,
+    #[serde(default = "default_bus")]
+    pub bus: String,
+
+    pub power: TimeSeries,
+    pub cost: TimeSeries,
+    #[serde(default)]
+    pub inputs: Vec<ConverterPort>,
+    #[serde(default)]
+    pub outputs: Vec<ConverterPort>,
+}
+
+impl Converter {
+    pub fn new(
+        cost: Vec<f64>,
+        power: Vec<f64>,
+
+        inputs: Vec<ConverterPort>,
+        outputs: Vec<ConverterPort>,
+
+        name: String,
+    ) -> Result<Self, Error> {
+        let converter = Converter {
+            name,
+            bus: DEFAULT_BUS.to_string(),
+            power: power.into(),
+            cost: cost.into(),
+            inputs,
+            outputs,
+        };
+        converter.validate()?;
+        Ok(converter)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        check_required(&self.name, "power", &self.power)?;
+        check_required(&self.name, "cost", &self.cost)?;
+        if self.inputs.is_empty() && self.outputs.is_empty() {
+            return Err(Error::invalid(
+                &self.name,
+                "inputs and outputs",
+                "must not both be empty",
+            ));
+        }
+        for port in self.inputs.iter().chain(&self.outputs) {
+            port.validate(&self.name)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_power(&self, timestep: usize) -> f64 {
+        self.power.get(timestep)
+    }
+
+    pub fn get_cost(&self, timestep: usize) -> f64 {
+        self.cost.get(timestep)
+    }
+}
+
+/// Transmission line connecting two buses.
+///
+/// `power_fwd` limits the flow from `from` to `to`, `power_bwd` the flow in the
+/// opposite direction. Losses are modelled by `eff`, which applies to the power
+/// leaving the sending bus. `cost` is charged per unit of transported power.
+/// `reactance` is only used when solving with DC load flow.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Line {
+    pub name: String,
+    pub from: String,
+    pub to: String,
+
+    pub power_fwd: TimeSeries,
+    /// Read as `power_fwd` if it is missing.
+    #[serde(default)]
+    pub power_bwd: TimeSeries,
+    pub eff: TimeSeries,
+    pub cost: TimeSeries,
+    pub reactance: Option<f64>,
+}
+
+impl Line {
+    pub fn new(
+        cost: Vec<f64>,
+        eff: Vec<f64>,
+        power_fwd: Vec<f64>,
+        power_bwd: Vec<f64>,
+
+        from: String,
+        to: String,
+
+        name: String,
+    ) -> Result<Self, Error> {
+        let line = Line {
+            name,
+            from,
+            to,
+            power_fwd: power_fwd.into(),
+            power_bwd: power_bwd.into(),
+            eff: eff.into(),
+            cost: cost.into(),
+            reactance: None,
+        };
+        line.validate()?;
+        Ok(line)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        check_required(&self.name, "power_fwd", &self.power_fwd)?;
+        check_required(&self.name, "power_bwd", &self.power_bwd)?;
+        check_eff_vec(&self.name, "eff", &self.eff)?;
+        check_required(&self.name, "cost", &self.cost)?;
+        if self.from == self.to {
+            return Err(Error::invalid(&self.name, "to", "must differ from from"));
+        }
+        if self.reactance.is_some_and(|reactance| reactance <= 0.0) {
+            return Err(Error::invalid(&self.name, "reactance", "must be positive"));
+        }
+        Ok(())
+    }
+
+    pub fn get_power_fwd(&self, timestep: usize) -> f64 {
+        self.power_fwd.get(timestep)
+    }
+
+    pub fn get_power_bwd(&self, timestep: usize) -> f64 {
+        self.power_bwd.get(timestep)
+    }
+
+    pub fn get_eff(&self, timestep: usize) -> f64 {
+        self.eff.get(timestep)
+    }
+
+    pub fn get_cost(&self, timestep: usize) -> f64 {
+        self.cost.get(timestep)
+    }
+}
+
+/// Point of common coupling shared by one or more grids.
+///
+/// The limits apply to the sum over all `grids`: `import_limit` and
+/// `export_limit` cap the net exchange in either direction, `exchange_limit`
+/// caps import plus export, e.g. for a shared transformer. Empty limits are
+/// unlimited.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionPoint {
+    pub name: String,
+    pub grids: Vec<String>,
+
+    #[serde(default)]
+    pub import_limit: TimeSeries,
+    #[serde(default)]
+    pub export_limit: TimeSeries,
+    #[serde(default)]
+    pub exchange_limit: TimeSeries,
+}
+
+impl ConnectionPoint {
+    pub fn new(
+        import_limit: Vec<f64>,
+        export_limit: Vec<f64>,
+        exchange_limit: Vec<f64>,
+
+        grids: Vec<String>,
+
+        name: String,
+    ) -> Result<Self, Error> {
+        let connection_point = ConnectionPoint {
+            name,
+            grids,
+            import_limit: import_limit.into(),
+            export_limit: export_limit.into(),
+            exchange_limit: exchange_limit.into(),
+        };
+        connection_point.validate()?;
+        Ok(connection_point)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        check_non_negative(&self.name, "import_limit", &self.import_limit)?;
+        check_non_negative(&self.name, "export_limit", &self.export_limit)?;
+        check_non_negative(&self.name, "exchange_limit", &self.exchange_limit)
+    }
+
+    pub fn get_import_limit(&self, timestep: usize) -> Option<f64> {
+        if self.import_limit.is_empty() {
+            return None;
+        }
+        Some(self.import_limit.get(timestep))
+    }
+
+    pub fn get_export_limit(&self, timestep: usize) -> Option<f64> {
+        if self.export_limit.is_empty() {
+            return None;
+        }
+        Some(self.export_limit.get(timestep))
+    }
+
+    pub fn get_exchange_limit(&self, timestep: usize) -> Option<f64> {
+        if self.exchange_limit.is_empty() {
+            return None;
+        }
+        Some(self.exchange_limit.get(timestep))
+    }
+}
+
+/// Any entity of the network. In JSON, an entity is an object whose
+/// `entity_type` names the variant. Missing optional fields take the defaults
+/// of the constructors and the entity is validated when it is read.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "TaggedEntity")]
 pub enum Entity {
     Consumer(Consumer),
     Producer(Producer),
     Storage(Storage),
     Grid(Grid),
+    Line(Line),
+    ConnectionPoint(ConnectionPoint),
+    ElectricVehicle(ElectricVehicle),
+    Converter(Converter),
+    /// Device defined outside of this crate. It is written to JSON, but cannot
+    /// be read back.
+    Custom(Box<dyn EnergyEntity>),
+}
+
+/// Entity as read from JSON, before the defaults and the validation.
+#[derive(Deserialize)]
+#[serde(tag = "entity_type")]
+enum TaggedEntity {
+    Consumer(Consumer),
+    Producer(Producer),
+    Storage(Storage),
+    Grid(Grid),
+    Line(Line),
+    ConnectionPoint(ConnectionPoint),
+    ElectricVehicle(ElectricVehicle),
+    Converter(Converter),
+}
+
+/// Entity as written to JSON.
+#[derive(Serialize)]
+#[serde(tag = "entity_type")]
+enum TaggedEntityRef<'a> {
+    Consumer(&'a Consumer),
+    Producer(&'a Producer),
+    Storage(&'a Storage),
+    Grid(&'a Grid),
+    Line(&'a Line),
+    ConnectionPoint(&'a ConnectionPoint),
+    ElectricVehicle(&'a ElectricVehicle),
+    Converter(&'a Converter),
+    Custom(#[serde(serialize_with = "result::serialize_object")] serde_json::Value),
+}
+
+impl TryFrom<TaggedEntity> for Entity {
+    type Error = Error;
+
+    fn try_from(tagged: TaggedEntity) -> Result<Self, Error> {
+        let entity = match tagged {
+            TaggedEntity::Consumer(consumer) => Entity::Consumer(consumer),
+            TaggedEntity::Producer(producer) => Entity::Producer(producer),
+            TaggedEntity::Storage(mut storage) => {
+                // A sized storage can hold at most the upper bound of its investment
+                if storage.storage_capacity == 0.0 {
+                    if let Some(invest_capacity) = &storage.invest_capacity {
+                        storage.storage_capacity = invest_capacity.max;
+                    }
+                }
+                Entity::Storage(storage)
+            }
+            TaggedEntity::Grid(grid) => Entity::Grid(grid),
+            TaggedEntity::Line(mut line) => {
+                if line.power_bwd.is_empty() {
+                    line.power_bwd = line.power_fwd.clone();
+                }
+                Entity::Line(line)
+            }
+            TaggedEntity::ConnectionPoint(connection_point) => {
+                Entity::ConnectionPoint(connection_point)
+            }
+            TaggedEntity::ElectricVehicle(electric_vehicle) => {
+                Entity::ElectricVehicle(electric_vehicle)
+            }
+            TaggedEntity::Converter(converter) => Entity::Converter(converter),
+        };
+        entity.validate()?;
+        Ok(entity)
+    }
+}
+
+impl Entity {
+    /// Checks the parameters of the entity, e.g. after changing its fields.
+    pub fn validate(&self) -> Result<(), Error> {
+        match self {
+            Entity::Consumer(consumer) => consumer.validate(),
+            Entity::Producer(producer) => producer.validate(),
+            Entity::Storage(storage) => storage.validate(),
+            Entity::Grid(grid) => grid.validate(),
+            Entity::Line(line) => line.validate(),
+            Entity::ConnectionPoint(connection_point) => connection_point.validate(),
+            Entity::ElectricVehicle(electric_vehicle) => electric_vehicle.validate(),
+            Entity::Converter(converter) => converter.validate(),
+            Entity::Custom(entity) => entity.validate(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Entity::Line(line) => &line.name,
+            Entity::ConnectionPoint(connection_point) => &connection_point.name,
+            entity => entity.as_energy_entity().map_or("", |entity| entity.name()),
+        }
+    }
+
+    /// The entity as an `EnergyEntity`. Lines and connection points couple
+    /// several buses or grids and are modelled separately.
+    pub fn as_energy_entity(&self) -> Option<&dyn EnergyEntity> {
+        match self {
+            Entity::Consumer(consumer) => Some(consumer),
+            Entity::Producer(producer) => Some(producer),
+            Entity::Storage(storage) => Some(storage),
+            Entity::Grid(grid) => Some(grid),
+            Entity::ElectricVehicle(electric_vehicle) => Some(electric_vehicle),
+            Entity::Converter(converter) => Some(converter),
+            Entity::Custom(entity) => Some(entity.as_ref()),
+            Entity::Line(_) | Entity::ConnectionPoint(_) => None,
+        }
+    }
+
+    /// Validates the entity and resamples its time series to `timesteps`
+    /// timesteps of `resolution` hours. Custom entities are returned as they
+    /// are.
+    ///
+    /// The getters of an entity, e.g. `Consumer::get_power_cons`, read series
+    /// with their own resolution in timesteps of one hour until the entity
+    /// is resampled.
+    pub fn resample(&self, resolution: f64, timesteps: usize) -> Result<Entity, Error> {
+        self.validate()?;
+
+        let mut entity = self.clone();
+        let series: Vec<&mut TimeSeries> = match &mut entity {
+            Entity::Consumer(consumer) => vec![
+                &mut consumer.power_cons,
+                &mut consumer.eff_cons,
+                &mut consumer.cost_cons,
+            ],
+            Entity::Producer(producer) => vec![
+                &mut producer.power_prod,
+                &mut producer.eff_prod,
+                &mut producer.cost_prod,
+                &mut producer.ramp_up,
+                &mut producer.ramp_down,
+                &mut producer.emission_factor,
+            ],
+            Entity::Storage(storage) => vec![
+                &mut storage.power_prod,
+                &mut storage.eff_prod,
+                &mut storage.cost_prod,
+                &mut storage.power_cons,
+                &mut storage.eff_cons,
+                &mut storage.cost_cons,
+                &mut storage.self_discharge,
+                &mut storage.ramp_up,
+                &mut storage.ramp_down,
+            ],
+            Entity::Grid(grid) => vec![
+                &mut grid.power_prod,
+                &mut grid.cost_prod,
+                &mut grid.power_cons,
+                &mut grid.cost_cons,
+                &mut grid.ramp_up,
+                &mut grid.ramp_down,
+                &mut grid.emission_factor,
+            ],
+            Entity::Line(line) => vec![
+                &mut line.power_fwd,
+                &mut line.power_bwd,
+                &mut line.eff,
+                &mut line.cost,
+            ],
+            Entity::ConnectionPoint(connection_point) => vec![
+                &mut connection_point.import_limit,
+                &mut connection_point.export_limit,
+                &mut connection_point.exchange_limit,
+            ],
+            Entity::ElectricVehicle(electric_vehicle) => vec![
+                &mut electric_vehicle.power_charge,
+                &mut electric_vehicle.eff_charge,
+                &mut electric_vehicle.power_discharge,
+                &mut electric_vehicle.eff_discharge,
+                &mut electric_vehicle.consumption,
+                &mut electric_vehicle.departure_soc,
+            ],
+            Entity::Converter(converter) => {
+                let ports = converter.inputs.iter_mut().chain(&mut converter.outputs);
+                let mut series: Vec<&mut TimeSeries> = ports.map(|port| &mut port.factor).collect();
+                series.push(&mut converter.power);
+                series.push(&mut converter.cost);
+                series
+            }
+            Entity::Custom(_) => vec![],
+        };
+        for series in series {
+            *series = series.resample(resolution, timesteps);
+        }
+        Ok(entity)
+    }
 }
@@ -1,239 +1,2183 @@
-use std::error::Error;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    str::FromStr,
+    time::Instant,
+};
 
-use good_lp::{highs, variable, variables, Constraint, Expression, Solution, SolverModel};
+#[cfg(feature = "highs")]
+use ::highs::HighsModelStatus;
+#[cfg(feature = "highs")]
+use good_lp::{highs, DualValues, SolutionWithDual};
+use good_lp::{
+    solvers::Solver, variable, Expression, IntoAffineExpression, ProblemVariables, ResolutionError,
+    Solution, SolverModel, Variable, VariableDefinition,
+};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
 
-use crate::Entity;
+use crate::{
+    model::{EnergyEntity, EntityVars, ModelResults, Row, TimestepModel},
+    result::{
+        ConnectionPointResult, ConsumerResult, ConverterResult, ElectricVehicleResult,
+        EntityResult, GridResult, LineResult, ProducerResult, StorageResult,
+    },
+    Consumer, Converter, ElectricVehicle, Entity, Error, Grid, Line, Producer, Storage,
+    DEFAULT_CARRIER,
+};
 
-pub fn solve(mut entities: Vec<Entity>, timesteps: usize) -> Result<Vec<Entity>, Box<dyn Error>> {
-    let mut to_minimize: Expression = 0.into();
+/// Absolute tolerance used to decide whether a ramp limit is binding.
+const RAMP_TOLERANCE: f64 = 1e-6;
 
-    let mut constraints: Vec<Constraint> = vec![];
-    let mut problem_vars = variables!();
+/// Cost per unit of relaxation when diagnosing an infeasible problem.
+const RELAXATION_PENALTY: f64 = 1e6;
 
-    for timestep in 0..timesteps {
-        let mut node_eq: Expression = 0.into();
+/// Smallest relaxation reported by the diagnosis.
+const RELAXATION_TOLERANCE: f64 = 1e-6;
 
-        let mut produced_eq: Expression = 0.into();
-        let mut consumed_eq: Expression = 0.into();
+/// Absolute tolerance used to check the solution of a solver that stopped early.
+const FEASIBILITY_TOLERANCE: f64 = 1e-5;
 
-        let mut produced_storage_eq: Expression = 0.into();
-        let mut consumed_storage_eq: Expression = 0.into();
+/// Solver used for the optimization. Every backend is only available if the
+/// cargo feature of the same name is enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SolverBackend {
+    Highs,
+    Cbc,
+    Scip,
+    Microlp,
+    /// Solves LPs only, so it cannot handle binary or integer variables.
+    Clarabel,
+}
 
-        for entity in entities.iter_mut() {
-            match entity {
-                Entity::Consumer(consumer) => {
-                    let consumed = problem_vars.add(variable().min(0).max(1.0));
+impl Default for SolverBackend {
+    /// HiGHS if it is enabled, otherwise the first enabled backend.
+    fn default() -> Self {
+        if cfg!(feature = "highs") {
+            SolverBackend::Highs
+        } else if cfg!(feature = "cbc") {
+            SolverBackend::Cbc
+        } else if cfg!(feature = "scip") {
+            SolverBackend::Scip
+        } else if cfg!(feature = "microlp") {
+            SolverBackend::Microlp
+        } else if cfg!(feature = "clarabel") {
+            SolverBackend::Clarabel
+        } else {
+            SolverBackend::Highs
+        }
+    }
+}
 
-                    consumer.consumed_var.push(consumed);
+impl SolverBackend {
+    fn supports_integers(&self) -> bool {
+        *self != SolverBackend::Clarabel
+    }
+}
 
-                    // Kirchhoff
-                    node_eq += consumed * -1.0 * consumer.get_power_cons(timestep)
-                        / consumer.get_eff_cons(timestep);
+impl fmt::Display for SolverBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SolverBackend::Highs => "highs",
+            SolverBackend::Cbc => "cbc",
+            SolverBackend::Scip => "scip",
+            SolverBackend::Microlp => "microlp",
+            SolverBackend::Clarabel => "clarabel",
+        };
+        write!(f, "{}", name)
+    }
+}
 
-                    // Consumers need the power demanded
-                    constraints.push((1.0 * consumed).eq(1.0));
+impl FromStr for SolverBackend {
+    type Err = String;
 
-                    consumed_eq += 1.0 *consumed * consumer.get_power_cons(timestep) / consumer.get_eff_cons(timestep);
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "highs" => Ok(SolverBackend::Highs),
+            "cbc" => Ok(SolverBackend::Cbc),
+            "scip" => Ok(SolverBackend::Scip),
+            "microlp" => Ok(SolverBackend::Microlp),
+            "clarabel" => Ok(SolverBackend::Clarabel),
+            _ => Err(format!("Unknown solver backend {}", name)),
+        }
+    }
+}
 
-                    to_minimize += consumed
-                        * consumer.get_cost_cons(timestep)
-                        * consumer.get_power_cons(timestep);
-                }
-                Entity::Producer(producer) => {
-                    let produced = problem_vars.add(variable().min(0).max(1.0));
+/// Settings of the model, e.g. the power flow and the emissions, and of the
+/// solver.
+#[derive(Debug, Clone, Default)]
+pub struct SolveOptions {
+    /// Restrict the flow on lines with a reactance to the DC load-flow solution.
+    /// Lines without a reactance stay freely controllable.
+    pub dc_power_flow: bool,
+    /// Bus whose voltage angle is fixed to zero. Defaults to the first bus
+    /// connected to a line with a reactance.
+    pub slack_bus: Option<String>,
+    /// Cost per unit of emissions, added to the objective.
+    pub carbon_price: f64,
+    /// Maximum emissions over the whole horizon.
+    pub emission_cap: Option<f64>,
+    /// Computes the prices from the duals of the energy balances, which needs
+    /// a second solve of the LP with the integers fixed. Prices are NaN if the
+    /// solver stopped before proving optimality.
+    pub prices: bool,
+    /// Length of a timestep in hours, 1 if `None`. Time series with their own
+    /// resolution are resampled to it.
+    pub resolution: Option<f64>,
+    /// Solver used for the optimization.
+    pub backend: SolverBackend,
+    /// Stops the solver after this many seconds and keeps the best solution
+    /// found so far. The bound of the gap is solved within the same budget.
+    pub time_limit: Option<f64>,
+    /// Relative MIP gap at which the solver stops.
+    pub mip_rel_gap: Option<f64>,
+    /// Absolute MIP gap at which the solver stops.
+    pub mip_abs_gap: Option<f64>,
+    /// Number of threads of the solver.
+    pub threads: Option<u32>,
+    /// Turns presolve on or off. Defaults to the choice of the solver.
+    pub presolve: Option<bool>,
+    /// Prints the log of the solver to stdout.
+    pub log: bool,
+}
+
+impl SolveOptions {
+    /// Length of a timestep in hours.
+    pub fn resolution(&self) -> f64 {
+        self.resolution.unwrap_or(1.0)
+    }
 
-                    producer.produced_var.push(produced);
+    /// Whether any setting of the solver itself differs from its default.
+    fn has_solver_settings(&self) -> bool {
+        self.time_limit.is_some()
+            || self.mip_rel_gap.is_some()
+            || self.mip_abs_gap.is_some()
+            || self.threads.is_some()
+            || self.presolve.is_some()
+            || self.log
+    }
 
-                    if !producer.can_be_disabled {
-                        constraints.push((1.0 * produced).eq(1.0));
-                    }
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.time_limit.is_some_and(|time_limit| time_limit <= 0.0) {
+            return Err(Error::invalid(
+                "SolveOptions",
+                "time_limit",
+                "must be positive",
+            ));
+        }
+        if self.mip_rel_gap.is_some_and(|gap| gap < 0.0) {
+            return Err(Error::invalid(
+                "SolveOptions",
+                "mip_rel_gap",
+                "must not be negative",
+            ));
+        }
+        if self.mip_abs_gap.is_some_and(|gap| gap < 0.0) {
+            return Err(Error::invalid(
+                "SolveOptions",
+                "mip_abs_gap",
+                "must not be negative",
+            ));
+        }
+        if self.threads == Some(0) {
+            return Err(Error::invalid(
+                "SolveOptions",
+                "threads",
+                "must be positive",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// How the solver stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SolveStatus {
+    /// Optimal within the MIP gaps.
+    Optimal,
+    /// Stopped by the time limit with a feasible solution.
+    TimeLimit,
+    /// Stopped for another reason with a feasible solution.
+    Feasible,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SolveResult {
+    /// Schedules of the entities, in the order of the input.
+    pub entities: Vec<EntityResult>,
+    /// Marginal price of energy per bus and timestep, taken from the duals of
+    /// the Kirchhoff constraints. Empty unless `SolveOptions::prices` is set.
+    /// Only HiGHS provides duals, other backends report NaN.
+    pub prices: BTreeMap<String, Vec<f64>>,
+    /// Emissions of all entities over the whole horizon.
+    pub emissions: f64,
+    /// Objective value per timestep. One-off costs such as investments and peak
+    /// charges count towards the first timestep they apply to.
+    pub cost: Vec<f64>,
+    /// Total cost over the horizon, the value of the objective.
+    pub objective: f64,
+    pub backend: SolverBackend,
+    pub status: SolveStatus,
+    /// Relative gap between the objective and the bound of the LP relaxation
+    /// if the solver stopped before proving optimality. It overestimates the
+    /// true MIP gap. `None` if the time limit left no time to solve the
+    /// relaxation.
+    pub gap: Option<f64>,
+}
+
+/// A constraint that needed relaxation to make the problem feasible.
+#[derive(Debug, Clone, Serialize)]
+pub struct Relaxation {
+    /// Kind of the constraint: `Kirchhoff`, `Storage empty`, `Storage
+    /// capacity`, `End capacity` or `Must run`.
+    pub constraint: String,
+    /// Entity of the constraint, or the node for `Kirchhoff`.
+    pub entity: String,
+    pub timestep: usize,
+    /// Energy by which the constraint is violated, positive if above and
+    /// negative if below its limit. Violations of power, i.e. of `Kirchhoff`
+    /// and `Must run`, are multiplied by the resolution. For `Kirchhoff` a
+    /// positive value is surplus and a negative value missing energy.
+    pub violation: f64,
+}
+
+/// Values of all variables after a solve, together with how the solver stopped.
+pub(crate) struct ModelSolution {
+    values: HashMap<Variable, f64>,
+    status: SolveStatus,
+    gap: Option<f64>,
+}
+
+impl Solution for ModelSolution {
+    fn value(&self, variable: Variable) -> f64 {
+        self.values[&variable]
+    }
+}
+
+/// Problem variables together with their definitions, so the MIP can be
+/// re-solved as an LP once the integers are known. Integers also keep their
+/// bounds, so they can be relaxed.
+#[derive(Default)]
+pub(crate) struct ModelVariables {
+    problem_vars: ProblemVariables,
+    definitions: Vec<(VariableDefinition, Option<(f64, f64)>)>,
+    variables: Vec<Variable>,
+    integers: Vec<Variable>,
+}
+
+impl ModelVariables {
+    pub(crate) fn add(&mut self, definition: VariableDefinition) -> Variable {
+        self.definitions.push((definition.clone(), None));
+        let variable = self.problem_vars.add(definition);
+        self.variables.push(variable);
+        variable
+    }
+
+    pub(crate) fn add_binary(&mut self) -> Variable {
+        self.add_integer(0.0, 1.0)
+    }
+
+    pub(crate) fn add_integer(&mut self, min: f64, max: f64) -> Variable {
+        let definition = variable().integer().min(min).max(max);
+        self.definitions
+            .push((definition.clone(), Some((min, max))));
+        let integer = self.problem_vars.add(definition);
+        self.variables.push(integer);
+        self.integers.push(integer);
+        integer
+    }
+
+    /// Minimises `objective` subject to `constraints` and returns the values of
+    /// all variables. `problem_vars` must hold the variables of this model, e.g.
+    /// taken from it or rebuilt.
+    ///
+    /// If the solver stops before proving optimality, the gap is measured
+    /// against the bound of the LP relaxation, solved in the remaining time.
+    fn solve(
+        &self,
+        problem_vars: ProblemVariables,
+        options: &SolveOptions,
+        objective: Expression,
+        constraints: &[Row],
+    ) -> Result<ModelSolution, Error> {
+        let start = Instant::now();
+        let backend = options.backend;
+        if !self.integers.is_empty() && !backend.supports_integers() {
+            return Err(Error::Solver(format!(
+                "The {} backend cannot solve problems with binary or integer variables, \
+                 which storages, grids, unit commitment and modular investments need",
+                backend
+            )));
+        }
+        options.validate()?;
+        if options.has_solver_settings() && backend != SolverBackend::Highs {
+            return Err(Error::Solver(format!(
+                "The {} backend does not support time limits, MIP gaps, threads, presolve or log settings",
+                backend
+            )));
+        }
+
+        let (values, status) = match backend {
+            #[cfg(feature = "highs")]
+            SolverBackend::Highs => solve_highs(
+                problem_vars,
+                &self.variables,
+                options,
+                objective.clone(),
+                constraints,
+            )?,
+            #[cfg(feature = "cbc")]
+            SolverBackend::Cbc => (
+                solve_using(
+                    good_lp::coin_cbc,
+                    problem_vars,
+                    &self.variables,
+                    objective.clone(),
+                    constraints,
+                )?,
+                SolveStatus::Optimal,
+            ),
+            #[cfg(feature = "scip")]
+            SolverBackend::Scip => (
+                solve_using(
+                    good_lp::scip,
+                    problem_vars,
+                    &self.variables,
+                    objective.clone(),
+                    constraints,
+                )?,
+                SolveStatus::Optimal,
+            ),
+            #[cfg(feature = "microlp")]
+            SolverBackend::Microlp => (
+                solve_using(
+                    good_lp::microlp,
+                    problem_vars,
+                    &self.variables,
+                    objective.clone(),
+                    constraints,
+                )?,
+                SolveStatus::Optimal,
+            ),
+            #[cfg(feature = "clarabel")]
+            SolverBackend::Clarabel => (
+                solve_using(
+                    good_lp::clarabel,
+                    problem_vars,
+                    &self.variables,
+                    objective.clone(),
+                    constraints,
+                )?,
+                SolveStatus::Optimal,
+            ),
+            #[allow(unreachable_patterns)]
+            backend => {
+                return Err(Error::Solver(format!(
+                    "The {} backend is not enabled, build with the feature {}",
+                    backend, backend
+                )))
+            }
+        };
 
-                    produced_eq += 1.0 * produced * producer.get_power_prod(timestep) / producer.get_eff_prod(timestep);
+        let mut solution = ModelSolution {
+            values,
+            status,
+            gap: None,
+        };
+        if status == SolveStatus::Optimal {
+            return Ok(solution);
+        }
+
+        // A solver that stopped early may not have found any feasible solution
+        let is_satisfied = constraints.iter().all(|row| {
+            let value = solution.eval(&row.expression);
+            match row.is_equality {
+                true => value.abs() <= FEASIBILITY_TOLERANCE,
+                false => value <= FEASIBILITY_TOLERANCE,
+            }
+        });
+        if !is_satisfied {
+            return Err(Error::Solver(format!(
+                "The solver stopped ({:?}) before finding a feasible solution",
+                status
+            )));
+        }
+
+        // The relaxation must not exceed the time limit either, the gap stays
+        // unknown if it cannot be solved in the remaining time
+        let remaining = options
+            .time_limit
+            .map(|time_limit| time_limit - start.elapsed().as_secs_f64());
+        if remaining.is_some_and(|remaining| remaining <= 0.0) {
+            return Ok(solution);
+        }
+        let relaxation_options = SolveOptions {
+            backend,
+            time_limit: remaining,
+            ..Default::default()
+        };
+        if let Ok(relaxation) = self.solve(
+            self.relaxed(),
+            &relaxation_options,
+            objective.clone(),
+            constraints,
+        ) {
+            if relaxation.status == SolveStatus::Optimal {
+                let (value, bound) = (solution.eval(&objective), relaxation.eval(&objective));
+                solution.gap = Some((value - bound).max(0.0) / value.abs().max(f64::EPSILON));
+            }
+        }
+
+        Ok(solution)
+    }
+
+    /// Returns the duals of `constraints` in the LP with every integer fixed to
+    /// its value in `solution`. Duals of a MIP are meaningless, so the LP is
+    /// solved again. Only HiGHS provides duals, other backends return NaN.
+    /// Returns `None` unless `options.prices` is set.
+    #[cfg_attr(not(feature = "highs"), allow(unused_variables))]
+    fn duals(
+        &self,
+        options: &SolveOptions,
+        objective: Expression,
+        constraints: &[Row],
+        solution: &ModelSolution,
+    ) -> Result<Option<Vec<f64>>, Error> {
+        if !options.prices {
+            return Ok(None);
+        }
+        // Without an optimal solution the duals are meaningless, and the fixed
+        // LP would run without the time limit
+        if solution.status != SolveStatus::Optimal {
+            return Ok(Some(vec![f64::NAN; constraints.len()]));
+        }
+
+        match options.backend {
+            #[cfg(feature = "highs")]
+            SolverBackend::Highs => {
+                let mut fixed_problem = self.fixed(solution).minimise(objective).using(highs);
+                let rows: Vec<_> = constraints
+                    .iter()
+                    .map(|row| fixed_problem.add_constraint(row.constraint()))
+                    .collect();
+
+                let mut fixed_solution = fixed_problem.solve()?;
+                let duals = fixed_solution.compute_dual();
+                Ok(Some(rows.into_iter().map(|row| duals.dual(row)).collect()))
+            }
+            _ => Ok(Some(vec![f64::NAN; constraints.len()])),
+        }
+    }
+
+    /// Recreates the variables in the same order.
+    fn rebuilt(&self) -> ProblemVariables {
+        let mut problem_vars = ProblemVariables::new();
+        for (definition, _) in self.definitions.iter() {
+            problem_vars.add(definition.clone());
+        }
+        problem_vars
+    }
+
+    /// Recreates the variables in the same order, with every integer replaced
+    /// by a continuous variable with the same bounds.
+    fn relaxed(&self) -> ProblemVariables {
+        let mut problem_vars = ProblemVariables::new();
+        for (definition, bounds) in self.definitions.iter() {
+            match bounds {
+                Some((min, max)) => problem_vars.add(variable().min(*min).max(*max)),
+                None => problem_vars.add(definition.clone()),
+            };
+        }
+        problem_vars
+    }
+
+    /// Recreates the variables in the same order, with every integer fixed to
+    /// its value in `solution`.
+    #[cfg(feature = "highs")]
+    fn fixed(&self, solution: &impl Solution) -> ProblemVariables {
+        let mut integers = self.integers.iter();
+        let mut problem_vars = ProblemVariables::new();
+
+        for (definition, bounds) in self.definitions.iter() {
+            if bounds.is_some() {
+                let value = solution.value(*integers.next().unwrap()).round();
+                problem_vars.add(variable().min(value).max(value));
+            } else {
+                problem_vars.add(definition.clone());
+            }
+        }
+
+        problem_vars
+    }
+}
+
+/// Flags the timesteps in which `output` changed by its full ramp limit.
+/// `initial` is the output before the first timestep, if it is known.
+fn ramp_binding(
+    output: &[f64],
+    initial: Option<f64>,
+    hours: f64,
+    ramp_limits: impl Fn(usize) -> (Option<f64>, Option<f64>),
+) -> Vec<bool> {
+    (0..output.len())
+        .map(|i| {
+            let previous = match i {
+                0 => match initial {
+                    Some(initial) => initial,
+                    None => return false,
+                },
+                _ => output[i - 1],
+            };
+            let change = output[i] - previous;
+            let (ramp_up, ramp_down) = ramp_limits(i);
+
+            ramp_up.is_some_and(|ramp_up| change >= ramp_up * hours - RAMP_TOLERANCE)
+                || ramp_down.is_some_and(|ramp_down| -change >= ramp_down * hours - RAMP_TOLERANCE)
+        })
+        .collect()
+}
+
+/// Minimises `objective` with `solver` and reads back the values of `variables`.
+#[cfg_attr(
+    not(any(
+        feature = "cbc",
+        feature = "scip",
+        feature = "microlp",
+        feature = "clarabel"
+    )),
+    allow(dead_code)
+)]
+fn solve_using<S>(
+    solver: S,
+    problem_vars: ProblemVariables,
+    variables: &[Variable],
+    objective: Expression,
+    constraints: &[Row],
+) -> Result<HashMap<Variable, f64>, ResolutionError>
+where
+    S: Solver,
+    S::Model: SolverModel<Error = ResolutionError>,
+{
+    let solution = constraints
+        .iter()
+        .map(Row::constraint)
+        .fold(
+            problem_vars.minimise(objective).using(solver),
+            |problem, constraint| problem.with(constraint),
+        )
+        .solve()?;
+
+    Ok(variables
+        .iter()
+        .map(|variable| (*variable, solution.value(*variable)))
+        .collect())
+}
+
+/// Solves with HiGHS directly, as good_lp does not report why HiGHS stopped.
+#[cfg(feature = "highs")]
+fn solve_highs(
+    problem_vars: ProblemVariables,
+    variables: &[Variable],
+    options: &SolveOptions,
+    objective: Expression,
+    constraints: &[Row],
+) -> Result<(HashMap<Variable, f64>, SolveStatus), ResolutionError> {
+    let mut model = constraints
+        .iter()
+        .map(Row::constraint)
+        .fold(
+            problem_vars.minimise(objective).using(highs),
+            |problem, constraint| problem.with(constraint),
+        )
+        .into_inner();
+
+    if let Some(time_limit) = options.time_limit {
+        model.set_option("time_limit", time_limit);
+    }
+    if let Some(mip_rel_gap) = options.mip_rel_gap {
+        model.set_option("mip_rel_gap", mip_rel_gap);
+    }
+    if let Some(mip_abs_gap) = options.mip_abs_gap {
+        model.set_option("mip_abs_gap", mip_abs_gap);
+    }
+    if let Some(threads) = options.threads {
+        model.set_option("threads", threads as i32);
+    }
+    if let Some(presolve) = options.presolve {
+        model.set_option("presolve", if presolve { "on" } else { "off" });
+    }
+    if options.log {
+        model.set_option("output_flag", true);
+        model.set_option("log_to_console", true);
+    }
+
+    let solved = model
+        .try_solve()
+        .map_err(|status| ResolutionError::Str(format!("HiGHS failed with {:?}", status)))?;
+    let status = match solved.status() {
+        HighsModelStatus::Optimal => SolveStatus::Optimal,
+        HighsModelStatus::ReachedTimeLimit => SolveStatus::TimeLimit,
+        HighsModelStatus::ReachedIterationLimit
+        | HighsModelStatus::ObjectiveBound
+        | HighsModelStatus::ObjectiveTarget
+        | HighsModelStatus::Unknown => SolveStatus::Feasible,
+        HighsModelStatus::Infeasible | HighsModelStatus::UnboundedOrInfeasible => {
+            return Err(ResolutionError::Infeasible)
+        }
+        HighsModelStatus::Unbounded => return Err(ResolutionError::Unbounded),
+        status => {
+            return Err(ResolutionError::Str(format!(
+                "HiGHS failed with {:?}",
+                status
+            )))
+        }
+    };
 
-                    node_eq += produced
-                        * producer.get_power_prod(timestep)
-                        * producer.get_eff_prod(timestep);
-                    to_minimize += produced
-                        * producer.get_cost_prod(timestep)
-                        * producer.get_power_prod(timestep);
+    let solution = solved.get_solution();
+    Ok((
+        variables
+            .iter()
+            .copied()
+            .zip(solution.columns().iter().copied())
+            .collect(),
+        status,
+    ))
+}
+
+/// Checks whether `constraints` can be satisfied, ignoring the objective.
+fn is_feasible<'a>(
+    problem_vars: &ModelVariables,
+    options: &SolveOptions,
+    constraints: impl Iterator<Item = &'a Row>,
+) -> bool {
+    let constraints: Vec<Row> = constraints.cloned().collect();
+    problem_vars
+        .solve(problem_vars.rebuilt(), options, 0.into(), &constraints)
+        .is_ok()
+}
+
+/// Returns the Kirchhoff expression of `node`, creating it on first use.
+fn bus_eq<'a>(node_eqs: &'a mut IndexMap<String, Expression>, node: &str) -> &'a mut Expression {
+    node_eqs.entry(node.to_string()).or_default()
+}
+
+/// Returns the voltage angle variable of `bus`, creating it on first use.
+fn bus_angle(
+    angles: &mut IndexMap<String, Variable>,
+    problem_vars: &mut ModelVariables,
+    bus: &str,
+) -> Variable {
+    *angles
+        .entry(bus.to_string())
+        .or_insert_with(|| problem_vars.add(variable()))
+}
+
+pub fn solve(entities: &[Entity], timesteps: usize) -> Result<Vec<EntityResult>, Error> {
+    solve_with_options(entities, timesteps, &SolveOptions::default()).map(|result| result.entities)
+}
+
+/// Rows and expressions of one set of entities within a model.
+struct EntityRows {
+    // Indices into the constraints, used to read back the dual values
+    kirchhoff_rows: IndexMap<String, Vec<usize>>,
+    connection_rows: IndexMap<String, Vec<usize>>,
+    vars: Vec<EntityVars>,
+    // The entities resampled to the timesteps of the model
+    entities: Vec<Entity>,
+
+    emissions_eq: Expression,
+    step_costs: Vec<Expression>,
+    resolution: f64,
+}
+
+/// Adds the variables and constraints of `entities` to a model. Their costs
+/// enter the objective multiplied by `weight`.
+fn add_entities(
+    entities: &[Entity],
+    timesteps: usize,
+    options: &SolveOptions,
+    weight: f64,
+    problem_vars: &mut ModelVariables,
+    constraints: &mut Vec<Row>,
+    to_minimize: &mut Expression,
+) -> Result<EntityRows, Error> {
+    if !(options.resolution() > 0.0 && options.resolution().is_finite()) {
+        return Err(Error::invalid(
+            "SolveOptions",
+            "resolution",
+            "must be positive",
+        ));
+    }
+    let entities = entities
+        .iter()
+        .map(|entity| entity.resample(options.resolution(), timesteps))
+        .collect::<Result<Vec<Entity>, Error>>()?;
+
+    let mut kirchhoff_rows: IndexMap<String, Vec<usize>> = IndexMap::new();
+    let mut connection_rows: IndexMap<String, Vec<usize>> = IndexMap::new();
+    let mut vars: Vec<EntityVars> = vec![EntityVars::default(); entities.len()];
+
+    let mut emissions_eq: Expression = 0.into();
+    let mut step_costs: Vec<Expression> = vec![];
+
+    for timestep in 0..timesteps {
+        let mut model = TimestepModel::new(timestep, timesteps, options, problem_vars, constraints);
+        let mut angles: IndexMap<String, Variable> = IndexMap::new();
+
+        for (entity, entity_vars) in entities.iter().zip(vars.iter_mut()) {
+            if let Entity::Line(line) = entity {
+                add_line(line, &mut model, entity_vars, &mut angles);
+            } else if let Some(entity) = entity.as_energy_entity() {
+                entity.add_timestep(&mut model, entity_vars)?;
+            }
+        }
+
+        // Connection points limit the exchange summed over their grids
+        for entity in entities.iter() {
+            if let Entity::ConnectionPoint(connection_point) = entity {
+                let mut net_import: Expression = 0.into();
+                let mut exchange: Expression = 0.into();
+
+                for name in connection_point.grids.iter() {
+                    let (grid, grid_vars) = entities
+                        .iter()
+                        .zip(&vars)
+                        .find_map(|(entity, grid_vars)| match entity {
+                            Entity::Grid(grid) if &grid.name == name => Some((grid, grid_vars)),
+                            _ => None,
+                        })
+                        .ok_or_else(|| {
+                            Error::invalid(
+                                &connection_point.name,
+                                "grids",
+                                format!("contain the unknown grid {}", name),
+                            )
+                        })?;
+
+                    let imported =
+                        grid_vars.get("produced")[timestep] * grid.get_power_prod(timestep);
+                    let exported =
+                        grid_vars.get("consumed")[timestep] * grid.get_power_cons(timestep);
+                    net_import += imported.clone() - exported.clone();
+                    exchange += imported + exported;
                 }
-                Entity::Storage(storage) => {
-                    let consumed = problem_vars.add(
-                        variable()
-                            .min(0)
-                            .max(1.0)
-                            .name(format!("{}-{}-c", storage.name, timestep)),
-                    );
-                    let produced = problem_vars.add(
-                        variable()
-                            .min(0)
-                            .max(1.0)
-                            .name(format!("{}-{}-p", storage.name, timestep)),
+
+                let rows = connection_rows
+                    .entry(connection_point.name.clone())
+                    .or_default();
+                let row_name = format!("Connection point {} @{}", connection_point.name, timestep);
+
+                if let Some(import_limit) = connection_point.get_import_limit(timestep) {
+                    rows.push(model.add_constraint(
+                        Row::leq(net_import.clone(), import_limit).named(row_name.clone()),
+                    ));
+                }
+                if let Some(export_limit) = connection_point.get_export_limit(timestep) {
+                    rows.push(model.add_constraint(
+                        Row::geq(net_import, -export_limit).named(row_name.clone()),
+                    ));
+                }
+                if let Some(exchange_limit) = connection_point.get_exchange_limit(timestep) {
+                    rows.push(
+                        model.add_constraint(Row::leq(exchange, exchange_limit).named(row_name)),
                     );
+                }
+            }
+        }
+
+        if options.dc_power_flow {
+            let slack_angle = match &options.slack_bus {
+                Some(bus) if !model.node_eqs.contains_key(bus) => {
+                    return Err(Error::invalid(
+                        "SolveOptions",
+                        "slack_bus",
+                        format!("names the unknown bus {}", bus),
+                    ));
+                }
+                Some(bus) => match angles.get(bus) {
+                    Some(angle) => Some(*angle),
+                    None => {
+                        return Err(Error::invalid(
+                            "SolveOptions",
+                            "slack_bus",
+                            format!("names the bus {}, which has no line with a reactance", bus),
+                        ));
+                    }
+                },
+                None => angles.values().next().copied(),
+            };
+
+            if let Some(angle) = slack_angle {
+                model.add_constraint(Row::eq(angle, 0).named(format!("Slack @{}", timestep)));
+            }
+        }
+
+        for (bus, node_eq) in std::mem::take(&mut model.node_eqs) {
+            let row = model.add_constraint(
+                Row::eq(node_eq, 0)
+                    .named(format!("Kirchhoff {} @{}", bus, timestep))
+                    .relaxable_power("Kirchhoff", &bus, timestep, model.options.resolution()),
+            );
+            kirchhoff_rows.entry(bus).or_default().push(row);
+        }
+
+        model.add_constraint(
+            Row::geq(model.production.clone(), model.storage_charge.clone())
+                .named(format!("Storage @{}", timestep)),
+        );
+        model.add_constraint(
+            Row::geq(model.consumption.clone(), model.storage_discharge.clone())
+                .named(format!("Storage @{}", timestep)),
+        );
+
+        let mut step_cost = model.cost;
+        step_cost += model.emissions.clone() * options.carbon_price;
+        emissions_eq += model.emissions;
+        *to_minimize += step_cost.clone() * weight;
+        step_costs.push(step_cost);
+    }
 
-                    storage.consumed_var.push(consumed);
-                    storage.produced_var.push(produced);
+    if let Some(emission_cap) = options.emission_cap {
+        constraints
+            .push(Row::leq(emissions_eq.clone(), emission_cap).named("Emission cap".to_string()));
+    }
 
-                    let mut storage_min_eq: Expression = 0.into();
-                    let mut storage_max_eq: Expression = 0.into();
+    Ok(EntityRows {
+        kirchhoff_rows,
+        connection_rows,
+        vars,
+        entities,
+        emissions_eq,
+        step_costs,
+        resolution: options.resolution(),
+    })
+}
 
-                    let producing = problem_vars.add(variable().binary());
-                    storage.producing_var.push(producing);
+/// Adds one timestep of `line`. Lines connect two buses, so they are not an
+/// `EnergyEntity`.
+fn add_line(
+    line: &Line,
+    model: &mut TimestepModel,
+    vars: &mut EntityVars,
+    angles: &mut IndexMap<String, Variable>,
+) {
+    let timestep = model.timestep;
 
-                    // Constraints to enforce mutual exclusivity
-                    constraints.push((1.0 * produced).leq(1.0 * producing)); // produced <= binary_var
-                    constraints.push((1.0 * consumed).leq(1.0 * (1.0 - producing))); // consumed <= 1 - binary_var
+    let fwd = model.add_variable(variable().min(0).max(1.0));
+    let bwd = model.add_variable(variable().min(0).max(1.0));
 
-                    storage_min_eq += storage.start_capacity;
-                    storage_max_eq += storage.start_capacity;
+    vars.push_scaled("fwd", fwd, line.get_power_fwd(timestep));
+    vars.push_scaled("bwd", bwd, line.get_power_bwd(timestep));
 
-                    consumed_eq += 1.0 * consumed * storage.get_power_cons(timestep) / storage.get_eff_cons(timestep);
-                    produced_eq += 1.0 * produced * storage.get_power_prod(timestep) / storage.get_eff_prod(timestep);
+    let forward = model.add_binary();
+    vars.push("forward", forward);
 
-                    if !storage.storage_to_grid_allowed {
-                        produced_storage_eq += 1.0 * produced * storage.get_power_prod(timestep) / storage.get_eff_prod(timestep);
-                    }
-                    if !storage.grid_to_storage_allowed {
-                        consumed_storage_eq += 1.0 * consumed * storage.get_power_cons(timestep) / storage.get_eff_cons(timestep);
-                    }
+    // Constraints to enforce a single flow direction
+    model.add_constraint(Row::leq(fwd, forward)); // fwd <= binary_var
+    model.add_constraint(Row::leq(bwd, 1.0 - forward)); // bwd <= 1 - binary_var
 
-                    // storage balance
-                    for j in 0..timestep + 1 {
-                        storage_min_eq += storage.consumed_var[j]
-                            * storage.get_eff_cons(j)
-                            * storage.get_power_cons(timestep)
-                            - storage.produced_var[j] * storage.get_power_prod(timestep);
-
-                        storage_max_eq += storage.consumed_var[j]
-                            * storage.get_eff_cons(j)
-                            * storage.get_power_cons(timestep)
-                            - storage.produced_var[j] * storage.get_power_prod(timestep);
-                    }
+    // Losses are taken from the power arriving at the receiving bus
+    *bus_eq(&mut model.node_eqs, &line.from) +=
+        bwd * line.get_power_bwd(timestep) * line.get_eff(timestep)
+            - fwd * line.get_power_fwd(timestep);
+    *bus_eq(&mut model.node_eqs, &line.to) +=
+        fwd * line.get_power_fwd(timestep) * line.get_eff(timestep)
+            - bwd * line.get_power_bwd(timestep);
 
-                    if storage.end_capacity.is_some() && timestep == timesteps - 1 {
-                        let mut end_storage_eq: Expression = 0.into();
-                        end_storage_eq += storage.start_capacity;
-                        for j in 0..timestep + 1 {
-                            end_storage_eq += storage.consumed_var[j]
-                                * storage.get_eff_cons(j)
-                                * storage.get_power_cons(timestep)
-                                - storage.produced_var[j] * storage.get_power_prod(timestep);
+    if let Some(reactance) = line.reactance.filter(|_| model.options.dc_power_flow) {
+        let angle_from = bus_angle(angles, model.problem_vars, &line.from);
+        let angle_to = bus_angle(angles, model.problem_vars, &line.to);
+
+        // DC load flow: the flow follows the voltage angle difference
+        model.add_constraint(
+            Row::eq(
+                fwd * line.get_power_fwd(timestep) - bwd * line.get_power_bwd(timestep),
+                (angle_from - angle_to) * (1.0 / reactance),
+            )
+            .named(format!("Load flow {} @{}", line.name, timestep)),
+        );
+    }
+
+    model.add_cost(
+        (fwd * line.get_power_fwd(timestep) + bwd * line.get_power_bwd(timestep))
+            * line.get_cost(timestep)
+            * model.options.resolution(),
+    );
+}
+
+/// Reads the results of the entities from the solution of a model.
+fn collect_results(
+    timesteps: usize,
+    entity_rows: EntityRows,
+    weight: f64,
+    backend: SolverBackend,
+    solution: &ModelSolution,
+    duals: Option<&[f64]>,
+) -> SolveResult {
+    // The duals of weighted costs are weighted as well, and the balances are
+    // in power while the costs are per energy
+    let resolution = entity_rows.resolution;
+    let prices: BTreeMap<String, Vec<f64>> = match duals {
+        Some(duals) => entity_rows
+            .kirchhoff_rows
+            .into_iter()
+            .map(|(bus, bus_rows)| {
+                (
+                    bus,
+                    bus_rows
+                        .iter()
+                        .map(|row| duals[*row] / weight / resolution)
+                        .collect(),
+                )
+            })
+            .collect(),
+        None => BTreeMap::new(),
+    };
+
+    let results = ModelResults {
+        solution,
+        duals,
+        weight,
+        prices: &prices,
+        resolution,
+    };
+
+    let mut entity_results: Vec<EntityResult> = vec![];
+    for (entity, vars) in entity_rows.entities.iter().zip(&entity_rows.vars) {
+        let result = if let Some(entity) = entity.as_energy_entity() {
+            entity.collect_results(vars, &results)
+        } else if let Entity::Line(line) = entity {
+            let mut result = LineResult {
+                name: line.name.clone(),
+                ..Default::default()
+            };
+            let (fwd_vars, bwd_vars) = (vars.get("fwd"), vars.get("bwd"));
+            for i in 0..fwd_vars.len() {
+                let flow = solution.value(fwd_vars[i]) * line.get_power_fwd(i)
+                    - solution.value(bwd_vars[i]) * line.get_power_bwd(i);
+
+                let capacity = if flow >= 0.0 {
+                    line.get_power_fwd(i)
+                } else {
+                    line.get_power_bwd(i)
+                };
+
+                result.flow.push(flow);
+                result.loading.push(if capacity > 0.0 {
+                    flow.abs() / capacity * 100.0
+                } else {
+                    0.0
+                });
+            }
+            EntityResult::Line(result)
+        } else {
+            EntityResult::ConnectionPoint(ConnectionPointResult {
+                name: entity.name().to_string(),
+                ..Default::default()
+            })
+        };
+        entity_results.push(result);
+    }
+
+    // The connection points are evaluated last as they need the results of the grids
+    for (index, entity) in entity_rows.entities.iter().enumerate() {
+        if let Entity::ConnectionPoint(connection_point) = entity {
+            let mut net_import = vec![0.0; timesteps];
+            let mut exchange = vec![0.0; timesteps];
+
+            for result in entity_results.iter() {
+                if let EntityResult::Grid(grid) = result {
+                    if connection_point.grids.contains(&grid.name) {
+                        for i in 0..timesteps {
+                            net_import[i] += grid.produced[i] - grid.consumed[i];
+                            exchange[i] += grid.produced[i] + grid.consumed[i];
                         }
-                        constraints.push(end_storage_eq.eq(storage.end_capacity.unwrap()));
                     }
+                }
+            }
+
+            if let EntityResult::ConnectionPoint(result) = &mut entity_results[index] {
+                result.net_import = net_import;
+                result.exchange = exchange;
+            }
+        }
+    }
 
-                    constraints.push(storage_min_eq.geq(0));
-                    constraints.push(storage_max_eq.leq(storage.storage_capacity));
+    let emissions = solution.eval(&entity_rows.emissions_eq);
+    let cost: Vec<f64> = entity_rows
+        .step_costs
+        .iter()
+        .map(|step_cost| solution.eval(step_cost))
+        .collect();
+    let objective = cost.iter().sum();
 
-                    node_eq += produced
-                        * storage.get_power_prod(timestep)
-                        * storage.get_eff_prod(timestep)
-                        - consumed * storage.get_power_cons(timestep) / storage.get_eff_cons(timestep);
+    SolveResult {
+        entities: entity_results,
+        prices,
+        emissions,
+        cost,
+        objective,
+        backend,
+        status: solution.status,
+        gap: solution.gap,
+    }
+}
 
-                    to_minimize += consumed
-                        * storage.get_cost_cons(timestep)
-                        * storage.get_power_cons(timestep)
-                        + produced
-                            * storage.get_cost_prod(timestep)
-                            * storage.get_power_prod(timestep);
-                }
-                Entity::Grid(grid) => {
-                    let consumed = problem_vars.add(variable().min(0).max(1.0));
-                    let produced = problem_vars.add(variable().min(0).max(1.0));
+pub fn solve_with_options(
+    entities: &[Entity],
+    timesteps: usize,
+    options: &SolveOptions,
+) -> Result<SolveResult, Error> {
+    let mut to_minimize: Expression = 0.into();
+
+    let mut constraints: Vec<Row> = vec![];
+    let mut problem_vars = ModelVariables::default();
+
+    let entity_rows = add_entities(
+        entities,
+        timesteps,
+        options,
+        1.0,
+        &mut problem_vars,
+        &mut constraints,
+        &mut to_minimize,
+    )?;
+
+    let variables = std::mem::take(&mut problem_vars.problem_vars);
+    let solution = problem_vars.solve(variables, options, to_minimize.clone(), &constraints);
+
+    match solution {
+        Ok(solution) => {
+            let duals = problem_vars.duals(options, to_minimize, &constraints, &solution)?;
+
+            Ok(collect_results(
+                timesteps,
+                entity_rows,
+                1.0,
+                options.backend,
+                &solution,
+                duals.as_deref(),
+            ))
+        }
+        Err(Error::Infeasible(None)) if !entity_rows.connection_rows.is_empty() => {
+            let is_feasible_without = |relaxed_rows: HashSet<usize>| {
+                let relaxed = constraints
+                    .iter()
+                    .enumerate()
+                    .filter(|(row, _)| !relaxed_rows.contains(row))
+                    .map(|(_, row)| row);
+                is_feasible(&problem_vars, options, relaxed)
+            };
+
+            // Name the connection points whose limits alone make the problem
+            // infeasible, or all of them if only their combination does
+            let mut names: Vec<&String> = entity_rows
+                .connection_rows
+                .iter()
+                .filter(|(_, rows)| is_feasible_without(rows.iter().copied().collect()))
+                .map(|(name, _)| name)
+                .collect();
+            if names.is_empty()
+                && is_feasible_without(
+                    entity_rows
+                        .connection_rows
+                        .values()
+                        .flatten()
+                        .copied()
+                        .collect(),
+                )
+            {
+                names = entity_rows.connection_rows.keys().collect();
+            }
+
+            if names.is_empty() {
+                return Err(Error::Infeasible(None));
+            }
+            let names: Vec<&str> = names.into_iter().map(String::as_str).collect();
+            Err(Error::Infeasible(Some(format!(
+                "The limits of connection point {} make the problem infeasible",
+                names.join(", ")
+            ))))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Finds the constraints that make a problem infeasible.
+///
+/// The Kirchhoff, storage capacity, empty storage, end capacity and must-run
+/// constraints are relaxed with slacks, which are penalized in the objective. Returns the
+/// constraints that still needed relaxation in the optimal solution. An empty
+/// list means that the problem is feasible.
+pub fn diagnose(
+    entities: &[Entity],
+    timesteps: usize,
+    options: &SolveOptions,
+) -> Result<Vec<Relaxation>, Error> {
+    let mut to_minimize: Expression = 0.into();
+    let mut constraints: Vec<Row> = vec![];
+    let mut problem_vars = ModelVariables::default();
+
+    add_entities(
+        entities,
+        timesteps,
+        options,
+        1.0,
+        &mut problem_vars,
+        &mut constraints,
+        &mut to_minimize,
+    )?;
+
+    let mut relaxed_rows = vec![];
+    for row in constraints.iter_mut() {
+        if row.relaxable.is_none() {
+            continue;
+        }
+
+        // An equality may be violated in both directions, an inequality only upwards
+        let above = problem_vars.add(variable().min(0));
+        let mut expression = row.expression.clone() - above;
+        to_minimize += above * RELAXATION_PENALTY;
+        if row.is_equality {
+            let below = problem_vars.add(variable().min(0));
+            expression += below;
+            to_minimize += below * RELAXATION_PENALTY;
+        }
+
+        relaxed_rows.push((
+            row.expression.clone(),
+            row.relaxable.take().unwrap(),
+            row.is_equality,
+        ));
+        row.expression = expression;
+    }
+
+    let variables = std::mem::take(&mut problem_vars.problem_vars);
+    let solution = problem_vars.solve(variables, options, to_minimize, &constraints)?;
 
-                    grid.consumed_var.push(consumed);
-                    grid.produced_var.push(produced);
+    Ok(relaxed_rows
+        .into_iter()
+        .filter_map(|(expression, relaxable, is_equality)| {
+            let violation = solution.eval(&expression) * relaxable.hours;
+            let is_violated = match is_equality {
+                true => violation.abs() > RELAXATION_TOLERANCE,
+                false => violation > RELAXATION_TOLERANCE,
+            };
+            is_violated.then(|| Relaxation {
+                constraint: relaxable.constraint.to_string(),
+                entity: relaxable.entity,
+                timestep: relaxable.timestep,
+                violation,
+            })
+        })
+        .collect())
+}
 
-                    let producing = problem_vars.add(variable().binary());
-                    grid.producing_var.push(producing);
+/// One outcome of the uncertain inputs of a stochastic problem.
+#[derive(Debug, Clone)]
+pub struct StochasticScenario {
+    pub name: String,
+    /// Probability of the scenario. Weights are normalized to sum to one.
+    pub weight: f64,
+    /// Entities of the scenario. Every scenario holds the same entities in the
+    /// same order and only differs in their arrays.
+    pub entities: Vec<Entity>,
+}
 
-                    // Constraints to enforce mutual exclusivity
-                    constraints.push((1.0 * produced).leq(1.0 * producing)); // produced <= binary_var
-                    constraints.push((1.0 * consumed).leq(1.0 * (1.0 - producing))); // consumed <= 1 - binary_var
+#[derive(Debug, Serialize)]
+pub struct ScenarioResult {
+    pub name: String,
+    /// Normalized weight of the scenario.
+    pub weight: f64,
+    #[serde(flatten)]
+    pub result: SolveResult,
+}
 
-                    node_eq += produced * grid.get_power_prod(timestep)
-                        - consumed * grid.get_power_cons(timestep);
+#[derive(Debug, Serialize)]
+pub struct StochasticResult {
+    pub scenarios: Vec<ScenarioResult>,
+    /// Weighted sum of the total cost of all scenarios.
+    pub expected_cost: f64,
+}
+
+/// Solves a two-stage stochastic problem that minimizes the expected cost over
+/// weighted scenarios.
+///
+/// The schedules of the entities named in `first_stage` are decided before the
+/// outcome is known and are therefore the same in all scenarios, e.g. a grid
+/// modelling day-ahead purchases. Invested capacities are always first-stage
+/// decisions. All other entities react to each scenario separately.
+pub fn solve_stochastic(
+    scenarios: &[StochasticScenario],
+    first_stage: &[String],
+    timesteps: usize,
+    options: &SolveOptions,
+) -> Result<StochasticResult, Error> {
+    if scenarios.is_empty() {
+        return Err(Error::invalid(
+            "solve_stochastic",
+            "scenarios",
+            "must not be empty",
+        ));
+    }
+    if let Some(scenario) = scenarios
+        .iter()
+        .find(|scenario| scenario.weight.is_nan() || scenario.weight <= 0.0)
+    {
+        return Err(Error::invalid(&scenario.name, "weight", "must be positive"));
+    }
+    let total_weight: f64 = scenarios.iter().map(|scenario| scenario.weight).sum();
 
-                    to_minimize += consumed
-                        * grid.get_cost_cons(timestep)
-                        * grid.get_power_cons(timestep)
-                        + produced * grid.get_cost_prod(timestep) * grid.get_power_prod(timestep);
+    let mut to_minimize: Expression = 0.into();
+    let mut constraints: Vec<Row> = vec![];
+    let mut problem_vars = ModelVariables::default();
+
+    let mut models = vec![];
+    for scenario in scenarios {
+        let weight = scenario.weight / total_weight;
+        let entity_rows = add_entities(
+            &scenario.entities,
+            timesteps,
+            options,
+            weight,
+            &mut problem_vars,
+            &mut constraints,
+            &mut to_minimize,
+        )?;
+        models.push((scenario, weight, entity_rows));
+    }
+
+    // Non-anticipativity: first-stage decisions equal those of the first scenario
+    let (first, others) = models.split_first().unwrap();
+    for (scenario, _, entity_rows) in others {
+        if scenario.entities.len() != first.0.entities.len() {
+            return Err(Error::invalid(
+                &scenario.name,
+                "entities",
+                "must be the same as in every other scenario",
+            ));
+        }
+        for (index, (entity, reference)) in
+            scenario.entities.iter().zip(&first.0.entities).enumerate()
+        {
+            let name = entity.name();
+            if name != reference.name() {
+                return Err(Error::invalid(
+                    &scenario.name,
+                    "entities",
+                    "must be the same as in every other scenario",
+                ));
+            }
+
+            // Decisions are compared as absolute values, as the scenarios may
+            // differ in the power of the entity
+            let (mut decisions, mut reference_decisions) = (
+                entity_rows.vars[index].decisions(),
+                first.2.vars[index].decisions(),
+            );
+            if !first_stage.iter().any(|first_stage| first_stage == name) {
+                decisions.retain(|kind, _| *kind == "capacity");
+                reference_decisions.retain(|kind, _| *kind == "capacity");
+            }
+
+            let is_same_structure = decisions.len() == reference_decisions.len()
+                && decisions.iter().all(|(kind, values)| {
+                    reference_decisions
+                        .get(kind)
+                        .is_some_and(|reference| reference.len() == values.len())
+                });
+            if !is_same_structure {
+                return Err(Error::invalid(
+                    &scenario.name,
+                    "entities",
+                    format!(
+                        "must give {} the same first-stage decisions as in every other scenario",
+                        name
+                    ),
+                ));
+            }
+            for (kind, values) in decisions {
+                for (value, reference) in values.into_iter().zip(reference_decisions[kind].iter()) {
+                    constraints.push(Row::eq(value, reference.clone()));
                 }
             }
         }
+    }
+
+    let variables = std::mem::take(&mut problem_vars.problem_vars);
+    let solution = problem_vars.solve(variables, options, to_minimize.clone(), &constraints)?;
+    let duals = problem_vars.duals(options, to_minimize, &constraints, &solution)?;
 
-        constraints.push(node_eq.eq(0).set_name(format!("Kirchhoff @{}", timestep)));
+    let scenarios: Vec<ScenarioResult> = models
+        .into_iter()
+        .map(|(scenario, weight, entity_rows)| ScenarioResult {
+            name: scenario.name.clone(),
+            weight,
+            result: collect_results(
+                timesteps,
+                entity_rows,
+                weight,
+                options.backend,
+                &solution,
+                duals.as_deref(),
+            ),
+        })
+        .collect();
+    let expected_cost = scenarios
+        .iter()
+        .map(|scenario| scenario.weight * scenario.result.objective)
+        .sum();
 
-        constraints.push(produced_eq.geq(consumed_storage_eq).set_name(format!("Storage @{}", timestep)));
-        constraints.push(consumed_eq.geq(produced_storage_eq).set_name(format!("Storage @{}", timestep)));
+    Ok(StochasticResult {
+        scenarios,
+        expected_cost,
+    })
+}
+
+impl EnergyEntity for Consumer {
+    fn name(&self) -> &str {
+        &self.name
     }
 
-    let solution = constraints.into_iter().fold(
-        problem_vars.minimise(to_minimize).using(highs),
-        |solution, constraint| solution.with(constraint),
-    );
+    fn validate(&self) -> Result<(), Error> {
+        Consumer::validate(self)
+    }
 
-    let solution = solution.solve();
+    fn add_timestep(&self, model: &mut TimestepModel, vars: &mut EntityVars) -> Result<(), Error> {
+        let timestep = model.timestep;
+        let hours = model.options.resolution();
+        let consumed = model.add_variable(variable().min(0).max(1.0));
 
-    match solution {
-        Ok(_) => {
-            let solution = solution.unwrap();
-
-            for entity in entities.iter_mut() {
-                match entity {
-                    Entity::Consumer(consumer) => {
-                        for (timestep, consumed_var) in consumer.consumed_var.iter().enumerate() {
-                            consumer.consumed.push(
-                                solution.value(*consumed_var) * consumer.get_power_cons(timestep),
-                            );
-                        }
-                    }
-                    Entity::Producer(producer) => {
-                        for (i, produced_var) in producer.produced_var.iter().enumerate() {
-                            producer
-                                .produced
-                                .push(solution.value(*produced_var) * producer.get_power_prod(i));
-                        }
-                    }
-                    Entity::Storage(storage) => {
-                        let n_entries = storage.produced_var.len();
+        vars.push_scaled("consumed", consumed, self.get_power_cons(timestep));
 
-                        let mut stored = storage.start_capacity;
+        // Demand that is not supplied is penalized with the value of lost load
+        let unserved: Expression = match self.value_of_lost_load {
+            Some(value_of_lost_load) => {
+                let unserved = model.add_variable(variable().min(0).max(1.0));
+                vars.push_scaled("unserved", unserved, self.get_power_cons(timestep));
+                model.add_cost(
+                    unserved * self.get_power_cons(timestep) * value_of_lost_load * hours,
+                );
+                unserved.into()
+            }
+            None => 0.into(),
+        };
 
-                        for i in 0..n_entries {
-                            storage.consumed.push(
-                                solution.value(storage.consumed_var[i]) * storage.get_power_cons(i),
-                            );
+        // Kirchhoff
+        model.add_to_node(
+            &self.bus,
+            &self.carrier,
+            consumed * -1.0 * self.get_power_cons(timestep) / self.get_eff_cons(timestep),
+        );
 
-                            storage.produced.push(
-                                solution.value(storage.produced_var[i])
-                                    * storage.get_power_prod(i)
-                                    * storage.get_eff_prod(i),
-                            );
+        match self.energy {
+            // Consumers need the power demanded
+            None => {
+                model.add_constraint(Row::eq(consumed + unserved, 1.0));
+            }
+            // Flexible consumers need the energy within their window
+            Some(energy) => {
+                let end = self
+                    .latest_end
+                    .unwrap_or(model.timesteps)
+                    .min(model.timesteps);
+                if self.earliest_start >= end {
+                    return Err(Error::invalid(
+                        &self.name,
+                        "earliest_start",
+                        "must be before latest_end and within the horizon",
+                    ));
+                }
 
-                            stored += storage.consumed[i] * storage.get_eff_cons(i)
-                                - storage.produced[i] / storage.get_eff_prod(i);
-                            storage.stored.push(stored);
-                        }
+                if (self.earliest_start..end).contains(&timestep) {
+                    model.add_constraint(Row::leq(consumed + unserved, 1.0));
+                } else {
+                    model.add_constraint(Row::eq(consumed + unserved, 0.0));
+                }
+
+                if timestep == end - 1 {
+                    let mut consumed_energy: Expression = (self.earliest_start..end)
+                        .map(|i| {
+                            let mut demand: Expression = vars.get("consumed")[i].into();
+                            if let Some(unserved) = vars.get("unserved").get(i) {
+                                demand += *unserved;
+                            }
+                            demand * self.get_power_cons(i) * hours
+                        })
+                        .sum();
+
+                    // Energy beyond what the window can take at `power_cons` is lost as well
+                    if let Some(value_of_lost_load) = self.value_of_lost_load {
+                        let excess = model.add_variable(variable().min(0));
+                        vars.push("excess", excess);
+                        model.add_cost(excess * value_of_lost_load);
+                        consumed_energy += excess;
                     }
-                    Entity::Grid(grid) => {
-                        for (i, consumed_var) in grid.consumed_var.iter().enumerate() {
-                            grid.consumed
-                                .push(solution.value(*consumed_var) * grid.get_power_cons(i));
+                    let energy_row = match self.min_energy {
+                        Some(min_energy) => {
+                            model.add_constraint(
+                                Row::geq(consumed_energy.clone(), min_energy)
+                                    .named(format!("Min energy {}", self.name)),
+                            );
+                            Row::leq(consumed_energy, energy)
                         }
+                        None => Row::eq(consumed_energy, energy),
+                    };
+                    model.add_constraint(energy_row.named(format!("Energy {}", self.name)));
+                }
+            }
+        }
 
-                        for (i, produced_var) in grid.produced_var.iter().enumerate() {
-                            grid.produced
-                                .push(solution.value(*produced_var) * grid.get_power_prod(i));
-                        }
-                    }
+        // The storage rules only refer to electricity
+        if self.carrier == DEFAULT_CARRIER {
+            model.add_consumption(
+                consumed * self.get_power_cons(timestep) / self.get_eff_cons(timestep),
+            );
+        }
+
+        model.add_cost(
+            consumed * self.get_cost_cons(timestep) * self.get_power_cons(timestep) * hours,
+        );
+        Ok(())
+    }
+
+    fn collect_results(&self, vars: &EntityVars, results: &ModelResults) -> EntityResult {
+        let consumed_vars = vars.get("consumed");
+        let unserved_vars = vars.get("unserved");
+
+        EntityResult::Consumer(ConsumerResult {
+            name: self.name.clone(),
+            consumed: consumed_vars
+                .iter()
+                .enumerate()
+                .map(|(i, consumed_var)| results.value(*consumed_var) * self.get_power_cons(i))
+                .collect(),
+            unserved: (0..consumed_vars.len())
+                .map(|i| match unserved_vars.get(i) {
+                    Some(unserved_var) => results.value(*unserved_var) * self.get_power_cons(i),
+                    None => 0.0,
+                })
+                .collect(),
+            excess: vars
+                .get("excess")
+                .iter()
+                .map(|excess_var| results.value(*excess_var))
+                .sum(),
+            price: results.price(&self.bus, &self.carrier),
+        })
+    }
+}
+
+impl EnergyEntity for Producer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        Producer::validate(self)
+    }
+
+    fn add_timestep(&self, model: &mut TimestepModel, vars: &mut EntityVars) -> Result<(), Error> {
+        let timestep = model.timestep;
+
+        // With an investment, `produced` is measured in units of installed capacity
+        let capacity: Expression = match &self.invest_power {
+            Some(investment) => {
+                if timestep == 0 {
+                    vars.capacity = Some(model.add_investment(investment));
                 }
+                vars.capacity.unwrap().into()
+            }
+            None => 1.0.into(),
+        };
+
+        let produced = match self.invest_power {
+            Some(_) => {
+                let produced = model.add_variable(variable().min(0));
+                model.add_constraint(Row::leq(produced, capacity.clone()));
+                produced
+            }
+            None => model.add_variable(variable().min(0).max(1.0)),
+        };
+
+        vars.push_scaled("produced", produced, self.get_power_prod(timestep));
+
+        if !self.can_be_disabled {
+            model.add_constraint(
+                Row::eq(
+                    produced * self.get_power_prod(timestep),
+                    capacity * self.get_power_prod(timestep),
+                )
+                .relaxable_power(
+                    "Must run",
+                    &self.name,
+                    timestep,
+                    model.options.resolution(),
+                ),
+            );
+        }
+
+        if self.has_unit_commitment() {
+            let on = model.add_binary();
+            let startup = model.add_binary();
+            let shutdown = model.add_binary();
+
+            vars.push("on", on);
+            vars.push("startup", startup);
+            vars.push("shutdown", shutdown);
+
+            // Output between the minimum stable load and full power while on
+            model.add_constraint(Row::leq(produced, on));
+            model.add_constraint(Row::geq(produced, on * self.min_load));
+
+            let was_on = match timestep {
+                0 => f64::from(u8::from(self.initially_on)).into_expression(),
+                _ => vars.get("on")[timestep - 1].into_expression(),
+            };
+            model.add_constraint(Row::eq(startup - shutdown, on - was_on));
+            model.add_constraint(Row::leq(startup + shutdown, 1.0));
+
+            // A start (stop) keeps the producer on (off) for the minimum up (down) time
+            if self.min_up_time > 1 {
+                let first = (timestep + 1).saturating_sub(self.min_up_time);
+                model.add_constraint(Row::leq(
+                    vars.get("startup")[first..]
+                        .iter()
+                        .copied()
+                        .sum::<Expression>(),
+                    on,
+                ));
+            }
+            if self.min_down_time > 1 {
+                let first = (timestep + 1).saturating_sub(self.min_down_time);
+                model.add_constraint(Row::leq(
+                    vars.get("shutdown")[first..]
+                        .iter()
+                        .copied()
+                        .sum::<Expression>(),
+                    1.0 - on,
+                ));
             }
+
+            model.add_cost(startup * self.startup_cost + shutdown * self.shutdown_cost);
+        }
+
+        let previous_output: Option<Expression> = match timestep {
+            0 => self.initial_output.map(Expression::from),
+            _ => Some(vars.get("produced")[timestep - 1] * self.get_power_prod(timestep - 1)),
+        };
+        if let Some(previous_output) = previous_output {
+            model.add_ramp_limits(
+                produced * self.get_power_prod(timestep),
+                previous_output,
+                self.get_ramp_up(timestep),
+                self.get_ramp_down(timestep),
+            );
+        }
+
+        if self.carrier == DEFAULT_CARRIER {
+            model.add_production(
+                produced * self.get_power_prod(timestep) / self.get_eff_prod(timestep),
+            );
+        }
+
+        model.add_to_node(
+            &self.bus,
+            &self.carrier,
+            produced * self.get_power_prod(timestep) * self.get_eff_prod(timestep),
+        );
+        let energy = produced * self.get_power_prod(timestep) * model.options.resolution();
+        model.add_cost(energy.clone() * self.get_cost_prod(timestep));
+        model.add_emissions(energy * self.get_emission_factor(timestep));
+        Ok(())
+    }
+
+    fn collect_results(&self, vars: &EntityVars, results: &ModelResults) -> EntityResult {
+        let mut result = ProducerResult {
+            name: self.name.clone(),
+            ..Default::default()
+        };
+
+        for (i, produced_var) in vars.get("produced").iter().enumerate() {
+            result
+                .produced
+                .push(results.value(*produced_var) * self.get_power_prod(i));
         }
-        Err(e) => {
-            return Err(Box::new(e));
+        for on_var in vars.get("on").iter() {
+            result.committed.push(results.value(*on_var) > 0.5);
+        }
+        if let (Some(capacity_var), Some(investment)) = (vars.capacity, &self.invest_power) {
+            result.invested_capacity = Some(results.value(capacity_var));
+            result.investment_cost = results.value(capacity_var) * investment.cost;
+        }
+        result.ramp_binding = ramp_binding(
+            &result.produced,
+            self.initial_output,
+            results.resolution(),
+            |i| (self.get_ramp_up(i), self.get_ramp_down(i)),
+        );
+        result.price = results.price(&self.bus, &self.carrier);
+        result.marginal_value = result
+            .price
+            .iter()
+            .enumerate()
+            .map(|(i, price)| price * self.get_eff_prod(i) - self.get_cost_prod(i))
+            .collect();
+        result.emissions = result
+            .produced
+            .iter()
+            .enumerate()
+            .map(|(i, produced)| produced * self.get_emission_factor(i) * results.resolution())
+            .collect();
+
+        EntityResult::Producer(result)
+    }
+}
+
+/// One timestep of a battery, shared by storages and electric vehicles.
+struct Battery<'a> {
+    name: &'a str,
+    /// Whether the battery can charge and discharge in this timestep.
+    is_available: bool,
+    can_discharge: bool,
+    /// Power drawn from the bus at `consumed` equal to 1.
+    power_cons: f64,
+    /// Power fed into the bus at `produced` equal to 1, before losses.
+    power_prod: f64,
+    /// Energy stored per unit of `consumed`.
+    charged_energy: f64,
+    /// Energy taken from the battery per unit of `produced`.
+    discharged_energy: f64,
+    /// Share of the previous state of charge lost in this timestep.
+    self_discharge: f64,
+    /// Energy taken from the battery without passing the bus, e.g. for driving.
+    drain: f64,
+    start_capacity: f64,
+    capacity: Expression,
+}
+
+impl Battery<'_> {
+    /// Adds the `consumed`, `produced` and `stored` variables of the timestep
+    /// and the balance of the stored energy. Returns the `consumed` and
+    /// `produced` variables, which are fractions of the power of the entity.
+    fn add_timestep(
+        self,
+        model: &mut TimestepModel,
+        vars: &mut EntityVars,
+    ) -> (Variable, Variable) {
+        let timestep = model.timestep;
+        let max = if self.is_available { 1.0 } else { 0.0 };
+        let max_produced = if self.can_discharge { max } else { 0.0 };
+
+        let consumed = model.add_variable(
+            variable()
+                .min(0)
+                .max(max)
+                .name(format!("{}-{}-c", self.name, timestep)),
+        );
+        let produced = model.add_variable(
+            variable()
+                .min(0)
+                .max(max_produced)
+                .name(format!("{}-{}-p", self.name, timestep)),
+        );
+
+        vars.push_scaled("consumed", consumed, self.power_cons);
+        vars.push_scaled("produced", produced, self.power_prod);
+
+        if self.can_discharge {
+            let producing = model.add_binary();
+            vars.push("producing", producing);
+
+            // Constraints to enforce mutual exclusivity
+            model.add_constraint(Row::leq(produced, producing)); // produced <= binary_var
+            model.add_constraint(Row::leq(consumed, 1.0 - producing)); // consumed <= 1 - binary_var
         }
+
+        // The lower bound is a row rather than a bound of the variable, so that
+        // `diagnose` can name a battery that is drained, e.g. by driving
+        let stored = model.add_variable(variable().name(format!("{}-{}-s", self.name, timestep)));
+        model.add_constraint(Row::geq(stored, 0).relaxable("Storage empty", self.name, timestep));
+
+        // storage balance, the previous state of charge decays by the self-discharge
+        let previous_stored: Expression = match timestep {
+            0 => self.start_capacity.into(),
+            _ => vars.get("stored")[timestep - 1].into(),
+        };
+        vars.push("stored", stored);
+        model.add_constraint(Row::eq(
+            stored,
+            previous_stored * (1.0 - self.self_discharge) + consumed * self.charged_energy
+                - produced * self.discharged_energy
+                - self.drain,
+        ));
+
+        let capacity_row = model.add_constraint(Row::leq(stored, self.capacity).relaxable(
+            "Storage capacity",
+            self.name,
+            timestep,
+        ));
+        vars.push_row("capacity", capacity_row);
+
+        (consumed, produced)
     }
+}
 
-    return Ok(entities);
+impl EnergyEntity for Storage {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        Storage::validate(self)
+    }
+
+    fn add_timestep(&self, model: &mut TimestepModel, vars: &mut EntityVars) -> Result<(), Error> {
+        let timestep = model.timestep;
+        let hours = model.options.resolution();
+
+        let storage_capacity: Expression = match &self.invest_capacity {
+            Some(investment) => {
+                if timestep == 0 {
+                    vars.capacity = Some(model.add_investment(investment));
+                }
+                vars.capacity.unwrap().into()
+            }
+            None => self.storage_capacity.into(),
+        };
+
+        let (consumed, produced) = Battery {
+            name: &self.name,
+            is_available: true,
+            can_discharge: true,
+            power_cons: self.get_power_cons(timestep),
+            power_prod: self.get_power_prod(timestep),
+            charged_energy: self.get_eff_cons(timestep) * self.get_power_cons(timestep) * hours,
+            discharged_energy: self.get_power_prod(timestep) * hours,
+            self_discharge: 1.0 - (1.0 - self.get_self_discharge(timestep)).powf(hours),
+            drain: 0.0,
+            start_capacity: self.start_capacity,
+            capacity: storage_capacity,
+        }
+        .add_timestep(model, vars);
+
+        if let Some(end_capacity) = self
+            .end_capacity
+            .filter(|_| timestep == model.timesteps - 1)
+        {
+            let stored = vars.get("stored")[timestep];
+            model.add_constraint(Row::eq(stored, end_capacity).relaxable(
+                "End capacity",
+                &self.name,
+                timestep,
+            ));
+        }
+
+        let previous_output: Option<Expression> = match timestep {
+            0 => self.initial_output.map(Expression::from),
+            _ => {
+                let previous = timestep - 1;
+                Some(
+                    vars.get("produced")[previous]
+                        * self.get_power_prod(previous)
+                        * self.get_eff_prod(previous)
+                        - vars.get("consumed")[previous] * self.get_power_cons(previous),
+                )
+            }
+        };
+        if let Some(previous_output) = previous_output {
+            model.add_ramp_limits(
+                produced * self.get_power_prod(timestep) * self.get_eff_prod(timestep)
+                    - consumed * self.get_power_cons(timestep),
+                previous_output,
+                self.get_ramp_up(timestep),
+                self.get_ramp_down(timestep),
+            );
+        }
+
+        if self.carrier == DEFAULT_CARRIER {
+            model.add_consumption(
+                consumed * self.get_power_cons(timestep) / self.get_eff_cons(timestep),
+            );
+            model.add_production(
+                produced * self.get_power_prod(timestep) / self.get_eff_prod(timestep),
+            );
+
+            if !self.storage_to_grid_allowed {
+                model.add_storage_discharge(
+                    produced * self.get_power_prod(timestep) / self.get_eff_prod(timestep),
+                );
+            }
+            if !self.grid_to_storage_allowed {
+                model.add_storage_charge(
+                    consumed * self.get_power_cons(timestep) / self.get_eff_cons(timestep),
+                );
+            }
+        }
+
+        model.add_to_node(
+            &self.bus,
+            &self.carrier,
+            produced * self.get_power_prod(timestep) * self.get_eff_prod(timestep)
+                - consumed * self.get_power_cons(timestep) / self.get_eff_cons(timestep),
+        );
+
+        model.add_cost(
+            (consumed * self.get_cost_cons(timestep) * self.get_power_cons(timestep)
+                + produced * self.get_cost_prod(timestep) * self.get_power_prod(timestep))
+                * hours,
+        );
+        Ok(())
+    }
+
+    fn collect_results(&self, vars: &EntityVars, results: &ModelResults) -> EntityResult {
+        let mut result = StorageResult {
+            name: self.name.clone(),
+            ..Default::default()
+        };
+
+        let (consumed_vars, produced_vars, stored_vars) = (
+            vars.get("consumed"),
+            vars.get("produced"),
+            vars.get("stored"),
+        );
+        for i in 0..produced_vars.len() {
+            result
+                .consumed
+                .push(results.value(consumed_vars[i]) * self.get_power_cons(i));
+            result.produced.push(
+                results.value(produced_vars[i]) * self.get_power_prod(i) * self.get_eff_prod(i),
+            );
+            result.stored.push(results.value(stored_vars[i]));
+        }
+        let output: Vec<f64> = result
+            .produced
+            .iter()
+            .zip(&result.consumed)
+            .map(|(p, c)| p - c)
+            .collect();
+        result.ramp_binding =
+            ramp_binding(&output, self.initial_output, results.resolution(), |i| {
+                (self.get_ramp_up(i), self.get_ramp_down(i))
+            });
+        result.price = results.price(&self.bus, &self.carrier);
+        result.capacity_value = -vars
+            .rows("capacity")
+            .iter()
+            .map(|row| results.dual(*row))
+            .sum::<f64>();
+        if let (Some(capacity_var), Some(investment)) = (vars.capacity, &self.invest_capacity) {
+            result.invested_capacity = Some(results.value(capacity_var));
+            result.investment_cost = results.value(capacity_var) * investment.cost;
+        }
+
+        EntityResult::Storage(result)
+    }
+}
+
+impl EnergyEntity for Grid {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        Grid::validate(self)
+    }
+
+    fn add_timestep(&self, model: &mut TimestepModel, vars: &mut EntityVars) -> Result<(), Error> {
+        let timestep = model.timestep;
+
+        let consumed = model.add_variable(variable().min(0).max(1.0));
+        let produced = model.add_variable(variable().min(0).max(1.0));
+
+        vars.push_scaled("consumed", consumed, self.get_power_cons(timestep));
+        vars.push_scaled("produced", produced, self.get_power_prod(timestep));
+
+        let producing = model.add_binary();
+        vars.push("producing", producing);
+
+        // Constraints to enforce mutual exclusivity
+        model.add_constraint(Row::leq(produced, producing)); // produced <= binary_var
+        model.add_constraint(Row::leq(consumed, 1.0 - producing)); // consumed <= 1 - binary_var
+
+        let previous_output: Option<Expression> = match timestep {
+            0 => self.initial_output.map(Expression::from),
+            _ => {
+                let previous = timestep - 1;
+                Some(
+                    vars.get("produced")[previous] * self.get_power_prod(previous)
+                        - vars.get("consumed")[previous] * self.get_power_cons(previous),
+                )
+            }
+        };
+        if let Some(previous_output) = previous_output {
+            model.add_ramp_limits(
+                produced * self.get_power_prod(timestep) - consumed * self.get_power_cons(timestep),
+                previous_output,
+                self.get_ramp_up(timestep),
+                self.get_ramp_down(timestep),
+            );
+        }
+
+        model.add_to_node(
+            &self.bus,
+            &self.carrier,
+            produced * self.get_power_prod(timestep) - consumed * self.get_power_cons(timestep),
+        );
+
+        let hours = model.options.resolution();
+        model.add_cost(
+            (consumed * self.get_cost_cons(timestep) * self.get_power_cons(timestep)
+                + produced * self.get_cost_prod(timestep) * self.get_power_prod(timestep))
+                * hours,
+        );
+        model.add_emissions(
+            produced * self.get_power_prod(timestep) * self.get_emission_factor(timestep) * hours,
+        );
+
+        // Demand charge on the maximum import of each billing period
+        if self.peak_cost != 0.0 {
+            if self.get_peak_period(timestep) == vars.get("peak").len() {
+                // The import before the first timestep already counts
+                let initial_peak = if timestep == 0 {
+                    self.initial_peak
+                } else {
+                    0.0
+                };
+                let peak = model.add_variable(variable().min(initial_peak));
+                vars.push("peak", peak);
+                model.add_cost(peak * self.peak_cost);
+            }
+            let peak = vars.get("peak")[self.get_peak_period(timestep)];
+            model.add_constraint(Row::geq(peak, produced * self.get_power_prod(timestep)));
+        }
+        Ok(())
+    }
+
+    fn collect_results(&self, vars: &EntityVars, results: &ModelResults) -> EntityResult {
+        let mut result = GridResult {
+            name: self.name.clone(),
+            ..Default::default()
+        };
+
+        for (i, consumed_var) in vars.get("consumed").iter().enumerate() {
+            result
+                .consumed
+                .push(results.value(*consumed_var) * self.get_power_cons(i));
+        }
+        for (i, produced_var) in vars.get("produced").iter().enumerate() {
+            result
+                .produced
+                .push(results.value(*produced_var) * self.get_power_prod(i));
+        }
+        let output: Vec<f64> = result
+            .produced
+            .iter()
+            .zip(&result.consumed)
+            .map(|(p, c)| p - c)
+            .collect();
+        result.ramp_binding =
+            ramp_binding(&output, self.initial_output, results.resolution(), |i| {
+                (self.get_ramp_up(i), self.get_ramp_down(i))
+            });
+
+        result.peak = self.peaks(&result.produced);
+        result.peak_charge = result.peak.iter().sum::<f64>() * self.peak_cost;
+        result.energy_cost = (0..result.produced.len())
+            .map(|i| {
+                (result.produced[i] * self.get_cost_prod(i)
+                    + result.consumed[i] * self.get_cost_cons(i))
+                    * results.resolution()
+            })
+            .sum();
+        result.price = results.price(&self.bus, &self.carrier);
+        result.emissions = result
+            .produced
+            .iter()
+            .enumerate()
+            .map(|(i, produced)| produced * self.get_emission_factor(i) * results.resolution())
+            .collect();
+
+        EntityResult::Grid(result)
+    }
+}
+
+impl EnergyEntity for ElectricVehicle {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        ElectricVehicle::validate(self)
+    }
+
+    fn add_timestep(&self, model: &mut TimestepModel, vars: &mut EntityVars) -> Result<(), Error> {
+        let timestep = model.timestep;
+        let hours = model.options.resolution();
+
+        // The vehicle can only charge or discharge while it is plugged in, and
+        // driving drains the battery while it is away
+        let (charge, discharge) = Battery {
+            name: &self.name,
+            is_available: self.is_available(timestep),
+            can_discharge: !self.power_discharge.is_empty(),
+            power_cons: self.get_power_charge(timestep),
+            power_prod: self.get_power_discharge(timestep),
+            charged_energy: self.get_power_charge(timestep) * self.get_eff_charge(timestep) * hours,
+            discharged_energy: self.get_power_discharge(timestep) * hours,
+            self_discharge: 0.0,
+            drain: if self.is_available(timestep) {
+                0.0
+            } else {
+                self.get_consumption(timestep) * hours
+            },
+            start_capacity: self.start_capacity,
+            capacity: self.battery_capacity.into(),
+        }
+        .add_timestep(model, vars);
+
+        // The energy stored before leaving must reach the departure target
+        if self.is_departure(timestep) {
+            let previous_stored: Expression = match timestep {
+                0 => self.start_capacity.into(),
+                _ => vars.get("stored")[timestep - 1].into(),
+            };
+            let target = self.get_departure_soc(timestep);
+            let departure_row = match self.shortfall_cost {
+                Some(shortfall_cost) => {
+                    let shortfall = model.add_variable(variable().min(0));
+                    vars.push("shortfall", shortfall);
+                    model.add_cost(shortfall * shortfall_cost);
+                    Row::geq(previous_stored + shortfall, target)
+                }
+                None => Row::geq(previous_stored, target),
+            };
+            model.add_constraint(
+                departure_row.named(format!("Departure {} @{}", self.name, timestep)),
+            );
+        }
+
+        model.add_to_node(
+            &self.bus,
+            DEFAULT_CARRIER,
+            discharge * self.get_power_discharge(timestep) * self.get_eff_discharge(timestep)
+                - charge * self.get_power_charge(timestep),
+        );
+        Ok(())
+    }
+
+    fn collect_results(&self, vars: &EntityVars, results: &ModelResults) -> EntityResult {
+        let mut result = ElectricVehicleResult {
+            name: self.name.clone(),
+            ..Default::default()
+        };
+
+        let (charge_vars, discharge_vars, stored_vars) = (
+            vars.get("consumed"),
+            vars.get("produced"),
+            vars.get("stored"),
+        );
+        let mut shortfall_vars = vars.get("shortfall").iter();
+
+        for i in 0..charge_vars.len() {
+            result
+                .charged
+                .push(results.value(charge_vars[i]) * self.get_power_charge(i));
+            result.discharged.push(
+                results.value(discharge_vars[i])
+                    * self.get_power_discharge(i)
+                    * self.get_eff_discharge(i),
+            );
+            result.stored.push(results.value(stored_vars[i]));
+
+            let shortfall = if self.is_departure(i) {
+                shortfall_vars
+                    .next()
+                    .map_or(0.0, |shortfall| results.value(*shortfall))
+            } else {
+                0.0
+            };
+            result.shortfall.push(shortfall);
+        }
+        result.price = results.price(&self.bus, DEFAULT_CARRIER);
+
+        EntityResult::ElectricVehicle(result)
+    }
+}
+
+impl EnergyEntity for Converter {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        Converter::validate(self)
+    }
+
+    fn add_timestep(&self, model: &mut TimestepModel, vars: &mut EntityVars) -> Result<(), Error> {
+        let timestep = model.timestep;
+
+        let activity = model.add_variable(variable().min(0).max(1.0));
+        vars.push_scaled("activity", activity, self.get_power(timestep));
+
+        // Inputs and outputs flow in fixed proportions to the activity
+        for input in self.inputs.iter() {
+            let consumed = activity * self.get_power(timestep) * input.get_factor(timestep);
+            model.add_to_node(&self.bus, &input.carrier, consumed.clone() * -1.0);
+            if input.carrier == DEFAULT_CARRIER {
+                model.add_consumption(consumed);
+            }
+        }
+        for output in self.outputs.iter() {
+            let produced = activity * self.get_power(timestep) * output.get_factor(timestep);
+            model.add_to_node(&self.bus, &output.carrier, produced.clone());
+            if output.carrier == DEFAULT_CARRIER {
+                model.add_production(produced);
+            }
+        }
+
+        model.add_cost(
+            activity
+                * self.get_power(timestep)
+                * self.get_cost(timestep)
+                * model.options.resolution(),
+        );
+        Ok(())
+    }
+
+    fn collect_results(&self, vars: &EntityVars, results: &ModelResults) -> EntityResult {
+        let mut result = ConverterResult {
+            name: self.name.clone(),
+            ..Default::default()
+        };
+
+        for (i, activity_var) in vars.get("activity").iter().enumerate() {
+            result
+                .activity
+                .push(results.value(*activity_var) * self.get_power(i));
+        }
+        // Ports with the same carrier add up
+        let timesteps = result.activity.len();
+        for input in self.inputs.iter() {
+            let flows = result
+                .consumed
+                .entry(input.carrier.clone())
+                .or_insert_with(|| vec![0.0; timesteps]);
+            for (i, activity) in result.activity.iter().enumerate() {
+                flows[i] += activity * input.get_factor(i);
+            }
+        }
+        for output in self.outputs.iter() {
+            let flows = result
+                .produced
+                .entry(output.carrier.clone())
+                .or_insert_with(|| vec![0.0; timesteps]);
+            for (i, activity) in result.activity.iter().enumerate() {
+                flows[i] += activity * output.get_factor(i);
+            }
+        }
+
+        EntityResult::Converter(result)
+    }
 }
@@ -0,0 +1,388 @@
+use std::{collections::BTreeMap, fmt};
+
+use good_lp::{
+    variable, Constraint, Expression, IntoAffineExpression, Solution, Variable, VariableDefinition,
+};
+use indexmap::IndexMap;
+use serde::Serialize;
+
+use crate::{
+    linprog::{ModelSolution, ModelVariables, SolveOptions},
+    node_name,
+    result::EntityResult,
+    Error, Investment,
+};
+
+/// Device that contributes to the optimization model.
+///
+/// The built-in devices implement it, and other devices can be added to a
+/// model as `Entity::Custom`. For every timestep in order, `add_timestep`
+/// creates the variables of the entity, adds its constraints, its flows into
+/// the energy balances of its bus and its costs. Once the model is solved,
+/// `collect_results` reads back the schedule.
+///
+/// The entity itself is not changed by a solve. The variables it creates are
+/// kept in `vars`, which is passed to every call of the same solve.
+pub trait EnergyEntity: fmt::Debug + EntityObject {
+    fn name(&self) -> &str;
+
+    /// Checks the parameters before the model is built.
+    fn validate(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn add_timestep(&self, model: &mut TimestepModel, vars: &mut EntityVars) -> Result<(), Error>;
+
+    fn collect_results(&self, vars: &EntityVars, results: &ModelResults) -> EntityResult;
+}
+
+/// Lets `Entity::Custom` be cloned and serialized. Implemented for every
+/// `EnergyEntity` that is `Clone` and `Serialize`.
+pub trait EntityObject {
+    fn clone_box(&self) -> Box<dyn EnergyEntity>;
+
+    /// The entity as a JSON object, or the error of its serializer.
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error>;
+}
+
+impl<T> EntityObject for T
+where
+    T: EnergyEntity + Clone + Serialize + 'static,
+{
+    fn clone_box(&self) -> Box<dyn EnergyEntity> {
+        Box::new(self.clone())
+    }
+
+    fn to_json(&self) -> Result<serde_json::Value, serde_json::Error> {
+        serde_json::to_value(self)
+    }
+}
+
+impl Clone for Box<dyn EnergyEntity> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Variables and rows that an entity added to one model, by kind, e.g. the
+/// `produced` variable of every timestep.
+///
+/// Stochastic problems share the decisions of first-stage entities between
+/// scenarios, and the invested `capacity` of every entity.
+#[derive(Debug, Clone, Default)]
+pub struct EntityVars {
+    vars: IndexMap<&'static str, Vec<Variable>>,
+    scales: IndexMap<&'static str, Vec<f64>>,
+    rows: IndexMap<&'static str, Vec<usize>>,
+    pub capacity: Option<Variable>,
+}
+
+impl EntityVars {
+    pub fn push(&mut self, kind: &'static str, variable: Variable) {
+        self.push_scaled(kind, variable, 1.0);
+    }
+
+    /// Pushes a variable that is a fraction of `scale`, e.g. `produced` as a
+    /// fraction of the power of a producer. Scenarios may differ in `scale`, so
+    /// their decisions are compared after scaling.
+    pub fn push_scaled(&mut self, kind: &'static str, variable: Variable, scale: f64) {
+        self.vars.entry(kind).or_default().push(variable);
+        self.scales.entry(kind).or_default().push(scale);
+    }
+
+    /// Variables of `kind` in the order they were pushed, empty if none were.
+    pub fn get(&self, kind: &str) -> &[Variable] {
+        self.vars.get(kind).map_or(&[], |vars| vars)
+    }
+
+    /// Remembers the index of a row, e.g. to read its dual value.
+    pub fn push_row(&mut self, kind: &'static str, row: usize) {
+        self.rows.entry(kind).or_default().push(row);
+    }
+
+    pub fn rows(&self, kind: &str) -> &[usize] {
+        self.rows.get(kind).map_or(&[], |rows| rows)
+    }
+
+    /// Every variable times its scale by kind and timestep, including the
+    /// capacity.
+    pub(crate) fn decisions(&self) -> IndexMap<&'static str, Vec<Expression>> {
+        let mut decisions: IndexMap<&'static str, Vec<Expression>> = self
+            .vars
+            .iter()
+            .map(|(kind, vars)| {
+                let scaled = vars
+                    .iter()
+                    .zip(&self.scales[kind])
+                    .map(|(var, scale)| *var * *scale)
+                    .collect();
+                (*kind, scaled)
+            })
+            .collect();
+        if let Some(capacity) = self.capacity {
+            decisions.insert("capacity", vec![capacity.into()]);
+        }
+        decisions
+    }
+}
+
+/// Marks a row that the diagnosis may relax.
+#[derive(Clone)]
+pub(crate) struct Relaxable {
+    pub(crate) constraint: &'static str,
+    pub(crate) entity: String,
+    pub(crate) timestep: usize,
+    /// Hours by which a violation is multiplied to give energy, 1 for rows
+    /// that are in energy already.
+    pub(crate) hours: f64,
+}
+
+/// Constraint of the form `expression == 0` or `expression <= 0`. Unlike
+/// `good_lp::Constraint` it can be cloned, so the same rows can be added to the
+/// MIP and to the LP with fixed binaries.
+#[derive(Clone)]
+pub struct Row {
+    pub(crate) expression: Expression,
+    pub(crate) is_equality: bool,
+    pub(crate) name: Option<String>,
+    pub(crate) relaxable: Option<Relaxable>,
+}
+
+impl Row {
+    pub fn eq(lhs: impl IntoAffineExpression, rhs: impl IntoAffineExpression) -> Self {
+        Row {
+            expression: lhs.into_expression() - rhs.into_expression(),
+            is_equality: true,
+            name: None,
+            relaxable: None,
+        }
+    }
+
+    pub fn leq(lhs: impl IntoAffineExpression, rhs: impl IntoAffineExpression) -> Self {
+        Row {
+            expression: lhs.into_expression() - rhs.into_expression(),
+            is_equality: false,
+            name: None,
+            relaxable: None,
+        }
+    }
+
+    pub fn geq(lhs: impl IntoAffineExpression, rhs: impl IntoAffineExpression) -> Self {
+        Row::leq(rhs, lhs)
+    }
+
+    pub fn named(mut self, name: String) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Lets `diagnose` relax the row. `constraint` is the kind of the row
+    /// reported in the `Relaxation`.
+    pub fn relaxable(mut self, constraint: &'static str, entity: &str, timestep: usize) -> Self {
+        self.relaxable = Some(Relaxable {
+            constraint,
+            entity: entity.to_string(),
+            timestep,
+            hours: 1.0,
+        });
+        self
+    }
+
+    /// Like `relaxable`, for a row in power that lasts `hours`, so that its
+    /// violation is reported as energy.
+    pub fn relaxable_power(
+        self,
+        constraint: &'static str,
+        entity: &str,
+        timestep: usize,
+        hours: f64,
+    ) -> Self {
+        let mut row = self.relaxable(constraint, entity, timestep);
+        if let Some(relaxable) = row.relaxable.as_mut() {
+            relaxable.hours = hours;
+        }
+        row
+    }
+
+    pub(crate) fn constraint(&self) -> Constraint {
+        let constraint = if self.is_equality {
+            self.expression.clone().eq(0)
+        } else {
+            self.expression.clone().leq(0)
+        };
+
+        match &self.name {
+            Some(name) => constraint.set_name(name.clone()),
+            None => constraint,
+        }
+    }
+}
+
+/// One timestep of the model, as seen by an entity.
+pub struct TimestepModel<'a> {
+    pub timestep: usize,
+    /// Length of the horizon.
+    pub timesteps: usize,
+    pub options: &'a SolveOptions,
+
+    pub(crate) problem_vars: &'a mut ModelVariables,
+    pub(crate) constraints: &'a mut Vec<Row>,
+
+    pub(crate) node_eqs: IndexMap<String, Expression>,
+    pub(crate) cost: Expression,
+    pub(crate) emissions: Expression,
+
+    // Electricity of the storage rules
+    pub(crate) production: Expression,
+    pub(crate) consumption: Expression,
+    pub(crate) storage_charge: Expression,
+    pub(crate) storage_discharge: Expression,
+}
+
+impl<'a> TimestepModel<'a> {
+    pub(crate) fn new(
+        timestep: usize,
+        timesteps: usize,
+        options: &'a SolveOptions,
+        problem_vars: &'a mut ModelVariables,
+        constraints: &'a mut Vec<Row>,
+    ) -> Self {
+        TimestepModel {
+            timestep,
+            timesteps,
+            options,
+            problem_vars,
+            constraints,
+            node_eqs: IndexMap::new(),
+            cost: 0.into(),
+            emissions: 0.into(),
+            production: 0.into(),
+            consumption: 0.into(),
+            storage_charge: 0.into(),
+            storage_discharge: 0.into(),
+        }
+    }
+
+    pub fn add_variable(&mut self, definition: VariableDefinition) -> Variable {
+        self.problem_vars.add(definition)
+    }
+
+    pub fn add_binary(&mut self) -> Variable {
+        self.problem_vars.add_binary()
+    }
+
+    /// Adds the installed capacity of an investment and charges its cost.
+    pub fn add_investment(&mut self, investment: &Investment) -> Variable {
+        let capacity = self.add_variable(variable().min(investment.min).max(investment.max));
+
+        // Only whole modules can be installed
+        if let Some(module_size) = investment.module_size {
+            let modules = self.problem_vars.add_integer(0.0, f64::INFINITY);
+            self.add_constraint(Row::eq(capacity, modules * module_size));
+        }
+
+        self.add_cost(capacity * investment.cost);
+        capacity
+    }
+
+    /// Adds a constraint and returns its index, which gives its dual value in
+    /// the results.
+    pub fn add_constraint(&mut self, row: Row) -> usize {
+        self.constraints.push(row);
+        self.constraints.len() - 1
+    }
+
+    /// Limits the change of `output` compared to `previous`, the output one
+    /// timestep earlier. The limits are per hour.
+    pub fn add_ramp_limits(
+        &mut self,
+        output: Expression,
+        previous: Expression,
+        ramp_up: Option<f64>,
+        ramp_down: Option<f64>,
+    ) {
+        let hours = self.options.resolution();
+        if let Some(ramp_up) = ramp_up {
+            self.add_constraint(Row::leq(output.clone() - previous.clone(), ramp_up * hours));
+        }
+        if let Some(ramp_down) = ramp_down {
+            self.add_constraint(Row::leq(previous - output, ramp_down * hours));
+        }
+    }
+
+    /// Adds `power` to the energy balance of `carrier` at `bus`. Power fed into
+    /// the bus is positive, power drawn from it negative.
+    pub fn add_to_node(&mut self, bus: &str, carrier: &str, power: Expression) {
+        *self.node_eqs.entry(node_name(bus, carrier)).or_default() += power;
+    }
+
+    pub fn add_cost(&mut self, cost: Expression) {
+        self.cost += cost;
+    }
+
+    pub fn add_emissions(&mut self, emissions: Expression) {
+        self.emissions += emissions;
+    }
+
+    /// Electricity produced on site, which may charge storages that cannot
+    /// charge from the grid.
+    pub fn add_production(&mut self, power: Expression) {
+        self.production += power;
+    }
+
+    /// Electricity consumed on site, which may be supplied by storages that
+    /// cannot feed the grid.
+    pub fn add_consumption(&mut self, power: Expression) {
+        self.consumption += power;
+    }
+
+    /// Charging of a storage that must be covered by the production on site.
+    pub fn add_storage_charge(&mut self, power: Expression) {
+        self.storage_charge += power;
+    }
+
+    /// Discharging of a storage that must be covered by the consumption on site.
+    pub fn add_storage_discharge(&mut self, power: Expression) {
+        self.storage_discharge += power;
+    }
+}
+
+/// Solution of a model, as seen by an entity.
+pub struct ModelResults<'a> {
+    pub(crate) solution: &'a ModelSolution,
+    pub(crate) duals: Option<&'a [f64]>,
+    pub(crate) weight: f64,
+    pub(crate) prices: &'a BTreeMap<String, Vec<f64>>,
+    pub(crate) resolution: f64,
+}
+
+impl ModelResults<'_> {
+    pub fn value(&self, variable: Variable) -> f64 {
+        self.solution.value(variable)
+    }
+
+    pub fn eval(&self, expression: &Expression) -> f64 {
+        self.solution.eval(expression)
+    }
+
+    /// Dual value of the constraint with `row` index. NaN if the prices were
+    /// not requested or the backend provides no duals.
+    pub fn dual(&self, row: usize) -> f64 {
+        // The duals of weighted costs are weighted as well
+        self.duals
+            .map_or(f64::NAN, |duals| duals[row] / self.weight)
+    }
+
+    /// Length of a timestep in hours.
+    pub fn resolution(&self) -> f64 {
+        self.resolution
+    }
+
+    /// Price of `carrier` at `bus` per unit of energy and timestep.
+    pub fn price(&self, bus: &str, carrier: &str) -> Vec<f64> {
+        self.prices
+            .get(&node_name(bus, carrier))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+/// Schedule of an entity after a solve. The entities themselves only describe
+/// the model, so the same entities can be solved many times.
+///
+/// Prices and the values derived from them are only computed if
+/// `SolveOptions::prices` is set. Otherwise the prices are empty and
+/// `capacity_value` is NaN.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "entity_type")]
+pub enum EntityResult {
+    Consumer(ConsumerResult),
+    Producer(ProducerResult),
+    Storage(StorageResult),
+    Grid(GridResult),
+    Line(LineResult),
+    ConnectionPoint(ConnectionPointResult),
+    ElectricVehicle(ElectricVehicleResult),
+    Converter(ConverterResult),
+    /// Result of an `Entity::Custom`, in the format chosen by the entity. Must
+    /// be a JSON object, anything else fails to serialize.
+    Custom(#[serde(serialize_with = "serialize_object")] serde_json::Value),
+}
+
+/// Serializes a custom entity or result, which must be a JSON object to carry
+/// the `entity_type` tag.
+pub(crate) fn serialize_object<S>(
+    value: &serde_json::Value,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    if !value.is_object() {
+        return Err(serde::ser::Error::custom(format!(
+            "Custom entities must be written as a JSON object, not {}",
+            value
+        )));
+    }
+    value.serialize(serializer)
+}
+
+impl EntityResult {
+    pub fn name(&self) -> &str {
+        match self {
+            EntityResult::Consumer(consumer) => &consumer.name,
+            EntityResult::Producer(producer) => &producer.name,
+            EntityResult::Storage(storage) => &storage.name,
+            EntityResult::Grid(grid) => &grid.name,
+            EntityResult::Line(line) => &line.name,
+            EntityResult::ConnectionPoint(connection_point) => &connection_point.name,
+            EntityResult::ElectricVehicle(electric_vehicle) => &electric_vehicle.name,
+            EntityResult::Converter(converter) => &converter.name,
+            EntityResult::Custom(value) => value["name"].as_str().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConsumerResult {
+    pub name: String,
+    pub consumed: Vec<f64>,
+    /// Demand that could not be supplied.
+    pub unserved: Vec<f64>,
+    /// Energy of a flexible consumer that its window cannot take at `power_cons`.
+    pub excess: f64,
+    /// Price of energy at the bus of the consumer.
+    pub price: Vec<f64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ProducerResult {
+    pub name: String,
+    pub produced: Vec<f64>,
+    /// Commitment schedule, only filled when unit commitment is used.
+    pub committed: Vec<bool>,
+    /// Timesteps in which a ramp limit is binding.
+    pub ramp_binding: Vec<bool>,
+    /// Price of energy at the bus of the producer.
+    pub price: Vec<f64>,
+    /// Margin per unit of produced power, i.e. the value of additional capacity.
+    pub marginal_value: Vec<f64>,
+    /// Emissions of the produced energy.
+    pub emissions: Vec<f64>,
+    /// Capacity chosen by the solver.
+    pub invested_capacity: Option<f64>,
+    pub investment_cost: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StorageResult {
+    pub name: String,
+    pub produced: Vec<f64>,
+    pub consumed: Vec<f64>,
+    /// State of charge at the end of each timestep.
+    pub stored: Vec<f64>,
+    /// Timesteps in which a ramp limit is binding.
+    pub ramp_binding: Vec<bool>,
+    /// Price of energy at the bus of the storage.
+    pub price: Vec<f64>,
+    /// Cost reduction per additional unit of storage capacity.
+    pub capacity_value: f64,
+    /// Capacity chosen by the solver.
+    pub invested_capacity: Option<f64>,
+    pub investment_cost: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GridResult {
+    pub name: String,
+    pub produced: Vec<f64>,
+    pub consumed: Vec<f64>,
+    /// Timesteps in which a ramp limit is binding.
+    pub ramp_binding: Vec<bool>,
+    /// Price of energy at the bus of the grid.
+    pub price: Vec<f64>,
+    /// Maximum import per billing period.
+    pub peak: Vec<f64>,
+    /// Demand charge of all billing periods.
+    pub peak_charge: f64,
+    /// Cost of the imported minus the revenue of the exported energy.
+    pub energy_cost: f64,
+    /// Emissions of the imported energy.
+    pub emissions: Vec<f64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LineResult {
+    pub name: String,
+    /// Net flow from `from` to `to`, measured at the sending bus.
+    pub flow: Vec<f64>,
+    /// Flow in percent of the capacity in the direction of the flow.
+    pub loading: Vec<f64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConnectionPointResult {
+    pub name: String,
+    /// Net import over all grids, negative when exporting.
+    pub net_import: Vec<f64>,
+    /// Import plus export over all grids.
+    pub exchange: Vec<f64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ElectricVehicleResult {
+    pub name: String,
+    /// Power drawn from the bus.
+    pub charged: Vec<f64>,
+    /// Power fed into the bus.
+    pub discharged: Vec<f64>,
+    pub stored: Vec<f64>,
+    /// Energy missing at the departure in this timestep.
+    pub shortfall: Vec<f64>,
+    /// Price of energy at the bus of the vehicle.
+    pub price: Vec<f64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ConverterResult {
+    pub name: String,
+    pub activity: Vec<f64>,
+    /// Input flows per carrier, summed over the ports of the same carrier.
+    pub consumed: BTreeMap<String, Vec<f64>>,
+    /// Output flows per carrier, summed over the ports of the same carrier.
+    pub produced: BTreeMap<String, Vec<f64>>,
+}
@@ -0,0 +1,608 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::{
+    linprog::{solve_with_options, SolveOptions, SolveStatus},
+    result::{
+        ConnectionPointResult, ConsumerResult, ConverterResult, ElectricVehicleResult,
+        EntityResult, GridResult, LineResult, ProducerResult, StorageResult,
+    },
+    Entity, Error, TimeSeries,
+};
+
+/// Settings of a rolling-horizon simulation.
+#[derive(Debug, Clone)]
+pub struct RollingHorizon {
+    /// Number of timesteps to simulate.
+    pub timesteps: usize,
+    /// Number of timesteps optimized in every solve.
+    pub look_ahead: usize,
+    /// Number of timesteps committed after every solve.
+    pub commit: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RollingResult {
+    /// Committed schedules of the entities over all timesteps.
+    pub entities: Vec<EntityResult>,
+    /// Marginal price of energy per bus in the committed timesteps.
+    pub prices: BTreeMap<String, Vec<f64>>,
+    /// Realized emissions over the whole horizon.
+    pub emissions: f64,
+    /// Realized cost per timestep.
+    pub cost: Vec<f64>,
+    /// `Optimal` unless a solve stopped early.
+    pub status: SolveStatus,
+    /// Largest gap of all solves that stopped early.
+    pub gap: Option<f64>,
+}
+
+/// Timesteps of a single solve. The first `commit` timesteps use the realized
+/// values, the remaining ones the forecast.
+struct Window {
+    start: usize,
+    commit: usize,
+    len: usize,
+}
+
+impl Window {
+    fn values<T: Copy>(&self, realized: &[T], forecast: &[T]) -> Vec<T> {
+        if realized.is_empty() {
+            return vec![];
+        }
+
+        (0..self.len)
+            .map(|i| {
+                let values = if i < self.commit || forecast.is_empty() {
+                    realized
+                } else {
+                    forecast
+                };
+                values[(self.start + i) % values.len()]
+            })
+            .collect()
+    }
+
+    /// Same as `values` for time series, which are already resampled to the
+    /// timesteps of the simulation.
+    fn series(&self, realized: &TimeSeries, forecast: &TimeSeries) -> TimeSeries {
+        if realized.is_empty() {
+            return TimeSeries::default();
+        }
+
+        let values = (0..self.len)
+            .map(|i| {
+                let series = if i < self.commit || forecast.is_empty() {
+                    realized
+                } else {
+                    forecast
+                };
+                series.get(self.start + i)
+            })
+            .collect();
+        TimeSeries::Explicit {
+            values,
+            resolution: None,
+        }
+    }
+}
+
+/// Simulates a controller that repeatedly optimizes `look_ahead` timesteps and
+/// commits the first `commit` of them.
+///
+/// `realized` and `forecast` hold the same entities in the same order. Each
+/// solve sees the realized values for the committed timesteps and the forecast
+/// for the rest of the look-ahead. The stored energy of storages and electric
+/// vehicles, the commitment of producers, the last output of entities with ramp
+/// limits and the remaining energy of flexible consumers are carried from one
+/// solve to the next.
+///
+/// An `emission_cap` applies to the whole horizon. Each solve is limited to
+/// the part of the cap that the committed timesteps before it left over.
+pub fn solve_rolling(
+    realized: &[Entity],
+    forecast: &[Entity],
+    rolling: &RollingHorizon,
+    options: &SolveOptions,
+) -> Result<RollingResult, Error> {
+    if realized.len() != forecast.len() {
+        return Err(Error::invalid(
+            "RollingHorizon",
+            "forecast",
+            "must contain the same entities as realized",
+        ));
+    }
+    if rolling.commit == 0 || rolling.commit > rolling.look_ahead {
+        return Err(Error::invalid(
+            "RollingHorizon",
+            "commit",
+            "must be between 1 and look_ahead",
+        ));
+    }
+
+    // Windows are cut from the time series at the resolution of the simulation
+    let resample = |entities: &[Entity]| {
+        entities
+            .iter()
+            .map(|entity| entity.resample(options.resolution(), rolling.timesteps))
+            .collect::<Result<Vec<Entity>, Error>>()
+    };
+    let mut state = resample(realized)?;
+    let forecast = resample(forecast)?;
+    let mut entities: Vec<EntityResult> = vec![];
+    let mut prices: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    let mut cost: Vec<f64> = vec![];
+    let mut status = SolveStatus::Optimal;
+    let mut gap: Option<f64> = None;
+    let mut emissions = 0.0;
+
+    let mut start = 0;
+    while start < rolling.timesteps {
+        let len = rolling.look_ahead.min(rolling.timesteps - start);
+        let window = Window {
+            start,
+            commit: rolling.commit.min(len),
+            len,
+        };
+
+        let window_entities = state
+            .iter()
+            .zip(&forecast)
+            .map(|(realized, forecast)| {
+                window_entity(
+                    realized,
+                    forecast,
+                    &window,
+                    rolling.timesteps,
+                    options.resolution(),
+                )
+            })
+            .collect::<Result<Vec<Entity>, Error>>()?;
+
+        let window_options = SolveOptions {
+            emission_cap: options
+                .emission_cap
+                .map(|emission_cap| (emission_cap - emissions).max(0.0)),
+            ..options.clone()
+        };
+        let result = solve_with_options(&window_entities, len, &window_options)?;
+
+        if entities.is_empty() {
+            entities = result.entities.iter().map(empty_result).collect();
+        }
+        for (index, solved) in result.entities.iter().enumerate() {
+            append_results(
+                &mut entities[index],
+                solved,
+                &window_entities[index],
+                &window,
+                options.resolution(),
+            );
+            carry_state(&mut state[index], solved, &window, options.resolution());
+        }
+        for (bus, bus_prices) in result.prices {
+            prices
+                .entry(bus)
+                .or_default()
+                .extend_from_slice(&bus_prices[..window.commit]);
+        }
+        let mut window_cost = result.cost[..window.commit].to_vec();
+        for (solved, window_entity) in result.entities.iter().zip(&window_entities) {
+            match (solved, window_entity) {
+                (EntityResult::Producer(solved), _) => {
+                    emissions += solved.emissions[..window.commit].iter().sum::<f64>()
+                }
+                (EntityResult::Grid(solved), Entity::Grid(window_grid)) => {
+                    emissions += solved.emissions[..window.commit].iter().sum::<f64>();
+
+                    // Peak charges of the window are replaced by those of the whole horizon
+                    for (period, peak) in solved.peak.iter().enumerate() {
+                        let first = window_grid.get_peak_period_start(period);
+                        if first < window.commit {
+                            window_cost[first] -= peak * window_grid.peak_cost;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        cost.extend_from_slice(&window_cost);
+        if result.status != SolveStatus::Optimal {
+            status = result.status;
+        }
+        gap = match (gap, result.gap) {
+            (Some(gap), Some(window_gap)) => Some(gap.max(window_gap)),
+            (gap, window_gap) => gap.or(window_gap),
+        };
+
+        start += window.commit;
+    }
+
+    for (entity, realized) in entities.iter_mut().zip(realized) {
+        if let (EntityResult::Grid(grid), Entity::Grid(realized)) = (entity, realized) {
+            // Peaks are evaluated over the whole horizon and charged in the
+            // first timestep of their billing period
+            grid.peak = realized.peaks(&grid.produced);
+            grid.peak_charge = grid.peak.iter().sum::<f64>() * realized.peak_cost;
+            for (period, peak) in grid.peak.iter().enumerate() {
+                cost[realized.get_peak_period_start(period)] += peak * realized.peak_cost;
+            }
+        }
+    }
+
+    Ok(RollingResult {
+        entities,
+        prices,
+        emissions,
+        cost,
+        status,
+        gap,
+    })
+}
+
+/// Returns the entity as seen by the solve of `window`, with timesteps of
+/// `hours`.
+fn window_entity(
+    realized: &Entity,
+    forecast: &Entity,
+    window: &Window,
+    timesteps: usize,
+    hours: f64,
+) -> Result<Entity, Error> {
+    let entity = match (realized, forecast) {
+        (Entity::Consumer(realized), Entity::Consumer(forecast)) => {
+            let mut consumer = realized.clone();
+            consumer.power_cons = window.series(&realized.power_cons, &forecast.power_cons);
+            consumer.eff_cons = window.series(&realized.eff_cons, &forecast.eff_cons);
+            consumer.cost_cons = window.series(&realized.cost_cons, &forecast.cost_cons);
+
+            // Only the solve that reaches the end of the window of the consumer
+            // has to consume all of the remaining energy. Earlier solves leave
+            // as much as the timesteps after the look-ahead can still take.
+            if let Some(energy) = consumer.energy {
+                let end = realized.latest_end.unwrap_or(timesteps);
+                let window_end = window.start + window.len;
+                if end <= window.start || realized.earliest_start >= window_end {
+                    consumer.energy = Some(0.0);
+                    consumer.earliest_start = 0;
+                    consumer.latest_end = None;
+                } else {
+                    consumer.earliest_start = realized.earliest_start.saturating_sub(window.start);
+                    consumer.latest_end = Some(end - window.start);
+                    if end > window_end {
+                        let power = if forecast.power_cons.is_empty() {
+                            realized
+                        } else {
+                            forecast
+                        };
+                        let later: f64 = (window_end.max(realized.earliest_start)..end)
+                            .map(|timestep| power.get_power_cons(timestep) * hours)
+                            .sum();
+                        consumer.min_energy = Some((energy - later).max(0.0));
+                    }
+                }
+            }
+            Entity::Consumer(consumer)
+        }
+        (Entity::Producer(realized), Entity::Producer(forecast)) => {
+            if realized.invest_power.is_some() {
+                return Err(Error::invalid(
+                    &realized.name,
+                    "invest_power",
+                    "cannot be used in a rolling horizon",
+                ));
+            }
+
+            let mut producer = realized.clone();
+            producer.power_prod = window.series(&realized.power_prod, &forecast.power_prod);
+            producer.eff_prod = window.series(&realized.eff_prod, &forecast.eff_prod);
+            producer.cost_prod = window.series(&realized.cost_prod, &forecast.cost_prod);
+            producer.ramp_up = window.series(&realized.ramp_up, &forecast.ramp_up);
+            producer.ramp_down = window.series(&realized.ramp_down, &forecast.ramp_down);
+            producer.emission_factor =
+                window.series(&realized.emission_factor, &forecast.emission_factor);
+            Entity::Producer(producer)
+        }
+        (Entity::Storage(realized), Entity::Storage(forecast)) => {
+            if realized.invest_capacity.is_some() {
+                return Err(Error::invalid(
+                    &realized.name,
+                    "invest_capacity",
+                    "cannot be used in a rolling horizon",
+                ));
+            }
+
+            let mut storage = realized.clone();
+            storage.power_prod = window.series(&realized.power_prod, &forecast.power_prod);
+            storage.eff_prod = window.series(&realized.eff_prod, &forecast.eff_prod);
+            storage.cost_prod = window.series(&realized.cost_prod, &forecast.cost_prod);
+            storage.power_cons = window.series(&realized.power_cons, &forecast.power_cons);
+            storage.eff_cons = window.series(&realized.eff_cons, &forecast.eff_cons);
+            storage.cost_cons = window.series(&realized.cost_cons, &forecast.cost_cons);
+            storage.self_discharge =
+                window.series(&realized.self_discharge, &forecast.self_discharge);
+            storage.ramp_up = window.series(&realized.ramp_up, &forecast.ramp_up);
+            storage.ramp_down = window.series(&realized.ramp_down, &forecast.ramp_down);
+
+            // The final level only applies to the solve reaching the end of the horizon
+            if window.start + window.len < timesteps {
+                storage.end_capacity = None;
+            }
+            Entity::Storage(storage)
+        }
+        (Entity::Grid(realized), Entity::Grid(forecast)) => {
+            let mut grid = realized.clone();
+            grid.power_prod = window.series(&realized.power_prod, &forecast.power_prod);
+            grid.cost_prod = window.series(&realized.cost_prod, &forecast.cost_prod);
+            grid.power_cons = window.series(&realized.power_cons, &forecast.power_cons);
+            grid.cost_cons = window.series(&realized.cost_cons, &forecast.cost_cons);
+            grid.ramp_up = window.series(&realized.ramp_up, &forecast.ramp_up);
+            grid.ramp_down = window.series(&realized.ramp_down, &forecast.ramp_down);
+            grid.emission_factor =
+                window.series(&realized.emission_factor, &forecast.emission_factor);
+            // Billing periods keep their place in the horizon, and the peak
+            // committed so far in the current one is already paid for
+            grid.peak_offset = realized.peak_offset + window.start;
+            Entity::Grid(grid)
+        }
+        (Entity::ElectricVehicle(realized), Entity::ElectricVehicle(forecast)) => {
+            let mut electric_vehicle = realized.clone();
+            electric_vehicle.power_charge =
+                window.series(&realized.power_charge, &forecast.power_charge);
+            electric_vehicle.eff_charge = window.series(&realized.eff_charge, &forecast.eff_charge);
+            electric_vehicle.power_discharge =
+                window.series(&realized.power_discharge, &forecast.power_discharge);
+            electric_vehicle.eff_discharge =
+                window.series(&realized.eff_discharge, &forecast.eff_discharge);
+            electric_vehicle.available = window.values(&realized.available, &forecast.available);
+            // A departure in the first timestep of the window is still enforced
+            if window.start > 0 {
+                electric_vehicle.initially_available = realized.is_available(window.start - 1);
+            }
+            electric_vehicle.consumption =
+                window.series(&realized.consumption, &forecast.consumption);
+            electric_vehicle.departure_soc =
+                window.series(&realized.departure_soc, &forecast.departure_soc);
+            Entity::ElectricVehicle(electric_vehicle)
+        }
+        (Entity::Converter(realized), Entity::Converter(forecast)) => {
+            let mut converter = realized.clone();
+            converter.power = window.series(&realized.power, &forecast.power);
+            converter.cost = window.series(&realized.cost, &forecast.cost);
+            for (port, forecast_port) in converter.inputs.iter_mut().zip(&forecast.inputs) {
+                port.factor = window.series(&port.factor, &forecast_port.factor);
+            }
+            for (port, forecast_port) in converter.outputs.iter_mut().zip(&forecast.outputs) {
+                port.factor = window.series(&port.factor, &forecast_port.factor);
+            }
+            Entity::Converter(converter)
+        }
+        (Entity::Line(realized), Entity::Line(forecast)) => {
+            let mut line = realized.clone();
+            line.power_fwd = window.series(&realized.power_fwd, &forecast.power_fwd);
+            line.power_bwd = window.series(&realized.power_bwd, &forecast.power_bwd);
+            line.eff = window.series(&realized.eff, &forecast.eff);
+            line.cost = window.series(&realized.cost, &forecast.cost);
+            Entity::Line(line)
+        }
+        (Entity::ConnectionPoint(realized), Entity::ConnectionPoint(forecast)) => {
+            let mut connection_point = realized.clone();
+            connection_point.import_limit =
+                window.series(&realized.import_limit, &forecast.import_limit);
+            connection_point.export_limit =
+                window.series(&realized.export_limit, &forecast.export_limit);
+            connection_point.exchange_limit =
+                window.series(&realized.exchange_limit, &forecast.exchange_limit);
+            Entity::ConnectionPoint(connection_point)
+        }
+        (Entity::Custom(realized), _) => {
+            return Err(Error::invalid(
+                realized.name(),
+                "entity",
+                "cannot be used in a rolling horizon",
+            ))
+        }
+        _ => {
+            return Err(Error::invalid(
+                "RollingHorizon",
+                "forecast",
+                "must contain the same entities as realized",
+            ))
+        }
+    };
+
+    Ok(entity)
+}
+
+/// Appends the committed part of `values` to `target`.
+fn extend<T: Clone>(target: &mut Vec<T>, values: &[T], commit: usize) {
+    target.extend_from_slice(&values[..commit.min(values.len())]);
+}
+
+/// Returns a result without any timesteps of the same entity as `solved`.
+fn empty_result(solved: &EntityResult) -> EntityResult {
+    let name = solved.name().to_string();
+    match solved {
+        EntityResult::Consumer(_) => EntityResult::Consumer(ConsumerResult {
+            name,
+            ..Default::default()
+        }),
+        EntityResult::Producer(_) => EntityResult::Producer(ProducerResult {
+            name,
+            ..Default::default()
+        }),
+        EntityResult::Storage(_) => EntityResult::Storage(StorageResult {
+            name,
+            ..Default::default()
+        }),
+        EntityResult::Grid(_) => EntityResult::Grid(GridResult {
+            name,
+            ..Default::default()
+        }),
+        EntityResult::Line(_) => EntityResult::Line(LineResult {
+            name,
+            ..Default::default()
+        }),
+        EntityResult::ConnectionPoint(_) => EntityResult::ConnectionPoint(ConnectionPointResult {
+            name,
+            ..Default::default()
+        }),
+        EntityResult::ElectricVehicle(_) => EntityResult::ElectricVehicle(ElectricVehicleResult {
+            name,
+            ..Default::default()
+        }),
+        EntityResult::Converter(_) => EntityResult::Converter(ConverterResult {
+            name,
+            ..Default::default()
+        }),
+        EntityResult::Custom(_) => solved.clone(),
+    }
+}
+
+/// Appends the results of the committed timesteps of `solved` to `entity`.
+/// `window_entity` is the entity as seen by the solve, with timesteps of
+/// `hours`.
+fn append_results(
+    entity: &mut EntityResult,
+    solved: &EntityResult,
+    window_entity: &Entity,
+    window: &Window,
+    hours: f64,
+) {
+    let commit = window.commit;
+
+    match (entity, solved) {
+        (EntityResult::Consumer(consumer), EntityResult::Consumer(solved)) => {
+            extend(&mut consumer.consumed, &solved.consumed, commit);
+            extend(&mut consumer.unserved, &solved.unserved, commit);
+            extend(&mut consumer.price, &solved.price, commit);
+            // Until the end of the window of the consumer is committed, the
+            // excess is carried on as remaining energy and solved again
+            if let Entity::Consumer(window_consumer) = window_entity {
+                if window_consumer.latest_end.is_some_and(|end| end <= commit) {
+                    consumer.excess += solved.excess;
+                }
+            }
+        }
+        (EntityResult::Producer(producer), EntityResult::Producer(solved)) => {
+            extend(&mut producer.produced, &solved.produced, commit);
+            extend(&mut producer.committed, &solved.committed, commit);
+            extend(&mut producer.ramp_binding, &solved.ramp_binding, commit);
+            extend(&mut producer.price, &solved.price, commit);
+            extend(&mut producer.marginal_value, &solved.marginal_value, commit);
+            extend(&mut producer.emissions, &solved.emissions, commit);
+        }
+        (EntityResult::Storage(storage), EntityResult::Storage(solved)) => {
+            extend(&mut storage.produced, &solved.produced, commit);
+            extend(&mut storage.consumed, &solved.consumed, commit);
+            extend(&mut storage.stored, &solved.stored, commit);
+            extend(&mut storage.ramp_binding, &solved.ramp_binding, commit);
+            extend(&mut storage.price, &solved.price, commit);
+        }
+        (EntityResult::Grid(grid), EntityResult::Grid(solved)) => {
+            if let Entity::Grid(window_grid) = window_entity {
+                for i in 0..commit {
+                    grid.energy_cost += (solved.produced[i] * window_grid.get_cost_prod(i)
+                        + solved.consumed[i] * window_grid.get_cost_cons(i))
+                        * hours;
+                }
+            }
+            extend(&mut grid.produced, &solved.produced, commit);
+            extend(&mut grid.consumed, &solved.consumed, commit);
+            extend(&mut grid.ramp_binding, &solved.ramp_binding, commit);
+            extend(&mut grid.price, &solved.price, commit);
+            extend(&mut grid.emissions, &solved.emissions, commit);
+        }
+        (
+            EntityResult::ElectricVehicle(electric_vehicle),
+            EntityResult::ElectricVehicle(solved),
+        ) => {
+            extend(&mut electric_vehicle.charged, &solved.charged, commit);
+            extend(&mut electric_vehicle.discharged, &solved.discharged, commit);
+            extend(&mut electric_vehicle.stored, &solved.stored, commit);
+            extend(&mut electric_vehicle.shortfall, &solved.shortfall, commit);
+            extend(&mut electric_vehicle.price, &solved.price, commit);
+        }
+        (EntityResult::Converter(converter), EntityResult::Converter(solved)) => {
+            extend(&mut converter.activity, &solved.activity, commit);
+            for (carrier, flows) in solved.consumed.iter() {
+                extend(
+                    converter.consumed.entry(carrier.clone()).or_default(),
+                    flows,
+                    commit,
+                );
+            }
+            for (carrier, flows) in solved.produced.iter() {
+                extend(
+                    converter.produced.entry(carrier.clone()).or_default(),
+                    flows,
+                    commit,
+                );
+            }
+        }
+        (EntityResult::Line(line), EntityResult::Line(solved)) => {
+            extend(&mut line.flow, &solved.flow, commit);
+            extend(&mut line.loading, &solved.loading, commit);
+        }
+        (
+            EntityResult::ConnectionPoint(connection_point),
+            EntityResult::ConnectionPoint(solved),
+        ) => {
+            extend(&mut connection_point.net_import, &solved.net_import, commit);
+            extend(&mut connection_point.exchange, &solved.exchange, commit);
+        }
+        _ => {}
+    }
+}
+
+/// Carries the state at the end of the committed timesteps, which last `hours`
+/// each, into the next solve.
+fn carry_state(state: &mut Entity, solved: &EntityResult, window: &Window, hours: f64) {
+    let last = window.commit - 1;
+
+    match (state, solved) {
+        (Entity::Consumer(consumer), EntityResult::Consumer(solved)) => {
+            if let Some(energy) = consumer.energy.as_mut() {
+                let supplied: f64 = (solved.consumed[..window.commit].iter().sum::<f64>()
+                    + solved.unserved[..window.commit].iter().sum::<f64>())
+                    * hours;
+                *energy = (*energy - supplied).max(0.0);
+            }
+        }
+        (Entity::Producer(producer), EntityResult::Producer(solved)) => {
+            if let Some(committed) = solved.committed.get(last) {
+                producer.initially_on = *committed;
+            }
+            producer.initial_output = Some(solved.produced[last]);
+        }
+        // Clamped, as the solver may exceed the bounds within its tolerance
+        (Entity::Storage(storage), EntityResult::Storage(solved)) => {
+            storage.start_capacity = solved.stored[last].clamp(0.0, storage.storage_capacity);
+            storage.initial_output = Some(solved.produced[last] - solved.consumed[last]);
+        }
+        (Entity::Grid(grid), EntityResult::Grid(solved)) => {
+            grid.initial_output = Some(solved.produced[last] - solved.consumed[last]);
+
+            // `initial_peak` holds the peak of the billing period the next
+            // solve starts in
+            let period = grid.get_peak_period(window.start + window.commit);
+            let mut peak = if grid.get_peak_period(window.start) == period {
+                grid.initial_peak
+            } else {
+                0.0
+            };
+            for (i, produced) in solved.produced[..window.commit].iter().enumerate() {
+                if grid.get_peak_period(window.start + i) == period {
+                    peak = peak.max(*produced);
+                }
+            }
+            grid.initial_peak = peak;
+        }
+        (Entity::ElectricVehicle(electric_vehicle), EntityResult::ElectricVehicle(solved)) => {
+            electric_vehicle.start_capacity =
+                solved.stored[last].clamp(0.0, electric_vehicle.battery_capacity);
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    linprog::{SolveOptions, SolverBackend, StochasticScenario},
+    rolling::RollingHorizon,
+    Entity, Error,
+};
+
+/// Input file of the solver.
+///
+/// Besides the entities and the modelling options, it can request a rolling
+/// horizon (`look_ahead`), a stochastic optimization (`scenarios`) or a
+/// diagnosis of an infeasible input.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scenario {
+    pub entities: Vec<Entity>,
+    pub timesteps: usize,
+    /// Length of a timestep in hours, 1 if `None`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolution: Option<f64>,
+
+    #[serde(default)]
+    pub dc_power_flow: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slack_bus: Option<String>,
+    #[serde(default)]
+    pub carbon_price: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emission_cap: Option<f64>,
+    /// Computes the prices from the duals of the energy balances.
+    #[serde(default)]
+    pub prices: bool,
+    /// Solver backend, the default backend if `None`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<SolverBackend>,
+
+    /// Number of timesteps optimized in every solve of a rolling horizon.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub look_ahead: Option<usize>,
+    /// Number of timesteps committed after every solve, 1 if `None`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit: Option<usize>,
+    /// Entities as seen by the controller, the realized entities if `None`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forecast: Option<Vec<Entity>>,
+
+    /// Scenarios of a stochastic optimization, which modify the entities.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scenarios: Option<Vec<ScenarioOverrides>>,
+    /// Entities decided before the outcome of the scenarios is known.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub first_stage: Vec<String>,
+
+    /// Relaxes the input to find out why it is infeasible instead of solving it.
+    #[serde(default)]
+    pub diagnose: bool,
+}
+
+/// Scenario of a stochastic optimization, given as changes to the entities.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScenarioOverrides {
+    pub name: String,
+    pub weight: f64,
+    /// Fields to replace, per entity name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub overrides: BTreeMap<String, BTreeMap<String, serde_json::Value>>,
+}
+
+impl Scenario {
+    pub fn new(entities: Vec<Entity>, timesteps: usize) -> Self {
+        Scenario {
+            entities,
+            timesteps,
+            resolution: None,
+            dc_power_flow: false,
+            slack_bus: None,
+            carbon_price: 0.0,
+            emission_cap: None,
+            prices: false,
+            backend: None,
+            look_ahead: None,
+            commit: None,
+            forecast: None,
+            scenarios: None,
+            first_stage: vec![],
+            diagnose: false,
+        }
+    }
+
+    /// Reads a scenario in the format of the solver binary.
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn options(&self) -> SolveOptions {
+        SolveOptions {
+            dc_power_flow: self.dc_power_flow,
+            slack_bus: self.slack_bus.clone(),
+            carbon_price: self.carbon_price,
+            emission_cap: self.emission_cap,
+            prices: self.prices,
+            resolution: self.resolution,
+            backend: self.backend.unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    /// The rolling horizon, if a `look_ahead` is given.
+    pub fn rolling_horizon(&self) -> Option<RollingHorizon> {
+        self.look_ahead.map(|look_ahead| RollingHorizon {
+            timesteps: self.timesteps,
+            look_ahead,
+            commit: self.commit.unwrap_or(1),
+        })
+    }
+
+    /// Applies the overrides of every scenario to a copy of the entities.
+    pub fn stochastic_scenarios(&self) -> Result<Vec<StochasticScenario>, Error> {
+        let entities = self
+            .entities
+            .iter()
+            .map(serde_json::to_value)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.scenarios
+            .iter()
+            .flatten()
+            .map(|scenario| {
+                let mut entities = entities.clone();
+                for (name, fields) in scenario.overrides.iter() {
+                    let entity = entities
+                        .iter_mut()
+                        .find(|entity| entity["name"] == name.as_str())
+                        .ok_or_else(|| {
+                            Error::invalid(
+                                &scenario.name,
+                                "overrides",
+                                format!("name the unknown entity {}", name),
+                            )
+                        })?;
+                    for (field, value) in fields {
+                        entity[field] = value.clone();
+                    }
+                }
+
+                Ok(StochasticScenario {
+                    name: scenario.name.clone(),
+                    weight: scenario.weight,
+                    entities: serde_json::from_value(serde_json::Value::Array(entities))?,
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,308 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+/// Parameter that changes over time.
+///
+/// `resolution` is the length of a value in hours. Without it, there is one
+/// value per timestep of the simulation, whatever its length. Series with a
+/// resolution are resampled to the timesteps of the simulation by averaging
+/// over every timestep.
+///
+/// In JSON, a plain array is a periodic series without a resolution and a
+/// number is a constant. The other definitions are objects tagged with their
+/// `type`, e.g. `{"type": "piecewise_linear", "points": [[0, 1], [6, 3]]}`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimeSeries {
+    Constant(f64),
+    /// Starts over after the last value.
+    Periodic {
+        values: Vec<f64>,
+        resolution: Option<f64>,
+    },
+    /// Keeps the last value after the end.
+    Explicit {
+        values: Vec<f64>,
+        resolution: Option<f64>,
+    },
+    /// Interpolates linearly between `(hour, value)` points and keeps the
+    /// first and last value outside of them.
+    PiecewiseLinear {
+        points: Vec<(f64, f64)>,
+    },
+}
+
+impl Default for TimeSeries {
+    fn default() -> Self {
+        TimeSeries::Periodic {
+            values: vec![],
+            resolution: None,
+        }
+    }
+}
+
+impl From<Vec<f64>> for TimeSeries {
+    fn from(values: Vec<f64>) -> Self {
+        TimeSeries::Periodic {
+            values,
+            resolution: None,
+        }
+    }
+}
+
+impl From<f64> for TimeSeries {
+    fn from(value: f64) -> Self {
+        TimeSeries::Constant(value)
+    }
+}
+
+impl TimeSeries {
+    /// Whether the series has no values. Optional parameters use an empty
+    /// series when they are not set.
+    pub fn is_empty(&self) -> bool {
+        match self {
+            TimeSeries::Constant(_) => false,
+            TimeSeries::Periodic { values, .. } | TimeSeries::Explicit { values, .. } => {
+                values.is_empty()
+            }
+            TimeSeries::PiecewiseLinear { points } => points.is_empty(),
+        }
+    }
+
+    /// Values the series is defined by. Every resampled value lies between
+    /// their minimum and maximum.
+    pub fn values(&self) -> Vec<f64> {
+        match self {
+            TimeSeries::Constant(value) => vec![*value],
+            TimeSeries::Periodic { values, .. } | TimeSeries::Explicit { values, .. } => {
+                values.clone()
+            }
+            TimeSeries::PiecewiseLinear { points } => {
+                points.iter().map(|(_, value)| *value).collect()
+            }
+        }
+    }
+
+    /// Checks the series of `field` of `entity`.
+    pub fn validate(&self, entity: &str, field: &str) -> Result<(), Error> {
+        match self {
+            TimeSeries::Periodic { resolution, .. } | TimeSeries::Explicit { resolution, .. } => {
+                if resolution
+                    .is_some_and(|resolution| !(resolution > 0.0 && resolution.is_finite()))
+                {
+                    return Err(Error::invalid(
+                        entity,
+                        field,
+                        "must have a positive resolution",
+                    ));
+                }
+            }
+            TimeSeries::PiecewiseLinear { points } => {
+                if points.iter().any(|(hour, _)| !hour.is_finite())
+                    || points.windows(2).any(|pair| pair[0].0 >= pair[1].0)
+                {
+                    return Err(Error::invalid(
+                        entity,
+                        field,
+                        "must have points at increasing hours",
+                    ));
+                }
+            }
+            TimeSeries::Constant(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Value in `timestep` of a simulation with timesteps of `resolution`
+    /// hours. An empty series is 0.
+    pub fn value(&self, timestep: usize, resolution: f64) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+
+        let start = timestep as f64 * resolution;
+        let end = start + resolution;
+        match self {
+            TimeSeries::Constant(value) => *value,
+            TimeSeries::Periodic {
+                values,
+                resolution: None,
+            } => values[timestep % values.len()],
+            TimeSeries::Explicit {
+                values,
+                resolution: None,
+            } => values[timestep.min(values.len() - 1)],
+            TimeSeries::Periodic {
+                values,
+                resolution: Some(native),
+            } => step_mean(|index| values[index % values.len()], *native, start, end),
+            TimeSeries::Explicit {
+                values,
+                resolution: Some(native),
+            } => step_mean(
+                |index| values[index.min(values.len() - 1)],
+                *native,
+                start,
+                end,
+            ),
+            TimeSeries::PiecewiseLinear { points } => linear_mean(points, start, end),
+        }
+    }
+
+    /// Value in `timestep` of a series that was resampled to the simulation.
+    ///
+    /// A series with its own resolution that was not resampled is read in
+    /// timesteps of one hour, e.g. in the getters of an entity before
+    /// `Entity::resample`.
+    pub fn get(&self, timestep: usize) -> f64 {
+        self.value(timestep, 1.0)
+    }
+
+    /// The series with one value per timestep of `resolution` hours.
+    /// Series without a resolution are kept as they are.
+    pub fn resample(&self, resolution: f64, timesteps: usize) -> TimeSeries {
+        match self {
+            TimeSeries::Periodic {
+                resolution: Some(_),
+                ..
+            }
+            | TimeSeries::Explicit {
+                resolution: Some(_),
+                ..
+            }
+            | TimeSeries::PiecewiseLinear { .. }
+                if !self.is_empty() =>
+            {
+                TimeSeries::Explicit {
+                    values: (0..timesteps)
+                        .map(|timestep| self.value(timestep, resolution))
+                        .collect(),
+                    resolution: None,
+                }
+            }
+            _ => self.clone(),
+        }
+    }
+}
+
+/// Mean of a step function with steps of `native` hours between the hours
+/// `start` and `end`.
+fn step_mean(step: impl Fn(usize) -> f64, native: f64, start: f64, end: f64) -> f64 {
+    let first = (start / native).floor() as usize;
+    let last = (end / native).ceil() as usize;
+    let area: f64 = (first..last)
+        .map(|index| {
+            let overlap = end.min((index + 1) as f64 * native) - start.max(index as f64 * native);
+            overlap.max(0.0) * step(index)
+        })
+        .sum();
+    area / (end - start)
+}
+
+/// Mean of the linear interpolation of `points` between the hours `start` and
+/// `end`.
+fn linear_mean(points: &[(f64, f64)], start: f64, end: f64) -> f64 {
+    // The interpolation is linear between the points inside the interval
+    let mut hours = vec![start];
+    hours.extend(
+        points
+            .iter()
+            .map(|(hour, _)| *hour)
+            .filter(|hour| *hour > start && *hour < end),
+    );
+    hours.push(end);
+
+    let area: f64 = hours
+        .windows(2)
+        .map(|pair| {
+            (interpolate(points, pair[0]) + interpolate(points, pair[1])) / 2.0
+                * (pair[1] - pair[0])
+        })
+        .sum();
+    area / (end - start)
+}
+
+fn interpolate(points: &[(f64, f64)], hour: f64) -> f64 {
+    let next = points.partition_point(|(point, _)| *point <= hour);
+    if next == 0 {
+        return points[0].1;
+    }
+    if next == points.len() {
+        return points[next - 1].1;
+    }
+
+    let ((hour_0, value_0), (hour_1, value_1)) = (points[next - 1], points[next]);
+    value_0 + (value_1 - value_0) * (hour - hour_0) / (hour_1 - hour_0)
+}
+
+/// Time series as tagged in JSON. Plain arrays and numbers are read separately.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum TaggedTimeSeries {
+    Constant {
+        value: f64,
+    },
+    Periodic {
+        values: Vec<f64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resolution: Option<f64>,
+    },
+    Explicit {
+        values: Vec<f64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        resolution: Option<f64>,
+    },
+    PiecewiseLinear {
+        points: Vec<(f64, f64)>,
+    },
+}
+
+impl Serialize for TimeSeries {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        // Keep the plain formats, so that older files are written back unchanged
+        let tagged = match self.clone() {
+            TimeSeries::Constant(value) => return value.serialize(serializer),
+            TimeSeries::Periodic {
+                values,
+                resolution: None,
+            } => return values.serialize(serializer),
+            TimeSeries::Periodic { values, resolution } => {
+                TaggedTimeSeries::Periodic { values, resolution }
+            }
+            TimeSeries::Explicit { values, resolution } => {
+                TaggedTimeSeries::Explicit { values, resolution }
+            }
+            TimeSeries::PiecewiseLinear { points } => TaggedTimeSeries::PiecewiseLinear { points },
+        };
+        tagged.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeSeries {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let series = match value {
+            serde_json::Value::Array(_) => Vec::<f64>::deserialize(value).map(TimeSeries::from),
+            serde_json::Value::Number(_) => f64::deserialize(value).map(TimeSeries::from),
+            _ => TaggedTimeSeries::deserialize(value).map(|tagged| match tagged {
+                TaggedTimeSeries::Constant { value } => TimeSeries::Constant(value),
+                TaggedTimeSeries::Periodic { values, resolution } => {
+                    TimeSeries::Periodic { values, resolution }
+                }
+                TaggedTimeSeries::Explicit { values, resolution } => {
+                    TimeSeries::Explicit { values, resolution }
+                }
+                TaggedTimeSeries::PiecewiseLinear { points } => {
+                    TimeSeries::PiecewiseLinear { points }
+                }
+            }),
+        };
+        series.map_err(serde::de::Error::custom)
+    }
+}
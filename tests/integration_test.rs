@@ -1,26 +1,45 @@
-use optimization::{linprog::solve, Consumer, Entity, Grid, Producer, Storage};
+// The baseline tests compare `is_ok()` with `true`
+#![allow(clippy::bool_assert_comparison)]
 
+use float_eq::assert_float_eq;
+use good_lp::variable;
+use optimization::{
+    linprog::{
+        diagnose, solve, solve_stochastic, solve_with_options, SolveOptions, SolveStatus,
+        SolverBackend, StochasticScenario,
+    },
+    model::{EnergyEntity, EntityVars, ModelResults, Row, TimestepModel},
+    result::{ElectricVehicleResult, EntityResult, LineResult},
+    rolling::{solve_rolling, RollingHorizon},
+    scenario::Scenario,
+    ConnectionPoint, Consumer, Converter, ConverterPort, ElectricVehicle, Entity, Error, Grid,
+    Investment, Line, Producer, Storage, TimeSeries,
+};
+use serde::Serialize;
 
 #[test]
 fn not_connected_grid() {
     let mut entities: Vec<Entity> = vec![];
     let timesteps = 4;
 
-    entities.push(Entity::Grid(Grid::new(
-        vec![0.0],
-        vec![1.0],
-        vec![0.0],
-        vec![1.0],
-        "grid".to_string(),
-    )));
-    
-    let result = solve(entities, timesteps);
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![1.0],
+            vec![0.0],
+            vec![1.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let result = solve(&entities, timesteps);
     assert_eq!(result.is_ok(), true);
 
     let mut unwrapped_result = result.unwrap();
 
     let grid = match unwrapped_result.pop().unwrap() {
-        Entity::Grid(grid) => grid,
+        EntityResult::Grid(grid) => grid,
         _ => panic!("Expected Grid"),
     };
 
@@ -30,25 +49,27 @@ fn not_connected_grid() {
 
 #[test]
 fn not_connected_grid_negative_price() {
-
     let mut entities: Vec<Entity> = vec![];
     let timesteps = 4;
 
-    entities.push(Entity::Grid(Grid::new(
-        vec![-1.0],
-        vec![1.0],
-        vec![0.0],
-        vec![1.0],
-        "grid".to_string(),
-    )));
-    
-    let result = solve(entities, timesteps);
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![-1.0],
+            vec![1.0],
+            vec![0.0],
+            vec![1.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let result = solve(&entities, timesteps);
     assert_eq!(result.is_ok(), true);
 
     let mut unwrapped_result = result.unwrap();
 
     let grid = match unwrapped_result.pop().unwrap() {
-        Entity::Grid(grid) => grid,
+        EntityResult::Grid(grid) => grid,
         _ => panic!("Expected Grid"),
     };
 
@@ -56,38 +77,37 @@ fn not_connected_grid_negative_price() {
     assert_eq!(grid.produced, vec![0.0, 0.0, 0.0, 0.0]);
 }
 
-
 #[test]
 fn grid_and_consumer() {
     let mut entities: Vec<Entity> = vec![];
     let timesteps = 4;
 
-    entities.push(Entity::Grid(Grid::new(
-        vec![0.0],
-        vec![1.0],
-        vec![0.0],
-        vec![1.0],
-        "grid".to_string(),
-    )));
-    entities.push(Entity::Consumer(Consumer::new(
-        vec![0.0],
-        vec![1.0],
-        vec![0.0, 1.0],
-        "consumer".to_string(),
-    )));
-    
-    let result = solve(entities, timesteps);
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![1.0],
+            vec![0.0],
+            vec![1.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+    entities.push(Entity::Consumer(
+        Consumer::new(vec![0.0], vec![1.0], vec![0.0, 1.0], "consumer".to_string()).unwrap(),
+    ));
+
+    let result = solve(&entities, timesteps);
     assert_eq!(result.is_ok(), true);
 
     let mut unwrapped_result = result.unwrap();
 
     let consumer = match unwrapped_result.pop().unwrap() {
-        Entity::Consumer(consumer) => consumer,
+        EntityResult::Consumer(consumer) => consumer,
         _ => panic!("Expected Consumer"),
     };
 
     let grid = match unwrapped_result.pop().unwrap() {
-        Entity::Grid(grid) => grid,
+        EntityResult::Grid(grid) => grid,
         _ => panic!("Expected Grid"),
     };
 
@@ -101,39 +121,39 @@ fn consumer_and_storage() {
     let mut entities: Vec<Entity> = vec![];
     let timesteps = 4;
 
-    entities.push(Entity::Storage(Storage::new(
-        vec![0.0],
-        vec![1.0],
-        vec![1.0],
-        vec![0.0],
-        vec![1.0],
-        vec![1.0],
-        20.0,
-        20.0,
-        None,
-        false,
-        false,
-        "storage".to_string(),
-    )));
-    entities.push(Entity::Consumer(Consumer::new(
-        vec![0.0],
-        vec![1.0],
-        vec![0.0, 1.0],
-        "consumer".to_string(),
-    )));
-    
-    let result = solve(entities, timesteps);
+    entities.push(Entity::Storage(
+        Storage::new(
+            vec![0.0],
+            vec![1.0],
+            vec![1.0],
+            vec![0.0],
+            vec![1.0],
+            vec![1.0],
+            20.0,
+            20.0,
+            None,
+            false,
+            false,
+            "storage".to_string(),
+        )
+        .unwrap(),
+    ));
+    entities.push(Entity::Consumer(
+        Consumer::new(vec![0.0], vec![1.0], vec![0.0, 1.0], "consumer".to_string()).unwrap(),
+    ));
+
+    let result = solve(&entities, timesteps);
     assert_eq!(result.is_ok(), true);
 
     let mut unwrapped_result = result.unwrap();
 
     let consumer = match unwrapped_result.pop().unwrap() {
-        Entity::Consumer(consumer) => consumer,
+        EntityResult::Consumer(consumer) => consumer,
         _ => panic!("Expected Consumer"),
     };
 
     let storage = match unwrapped_result.pop().unwrap() {
-        Entity::Storage(storage) => storage,
+        EntityResult::Storage(storage) => storage,
         _ => panic!("Expected Storage"),
     };
 
@@ -145,84 +165,87 @@ fn consumer_and_storage() {
 
 #[test]
 fn consumer_and_producer() {
-
     let mut entities: Vec<Entity> = vec![];
     let timesteps = 4;
 
-    entities.push(Entity::Producer(Producer::new(
-        vec![0.0],
-        vec![1.0],
-        vec![0.0, 1.0],
-        true,
-        "producer".to_string(),
-    )));
-    entities.push(Entity::Consumer(Consumer::new(
-        vec![0.0],
-        vec![1.0],
-        vec![0.0, 1.0],
-        "consumer".to_string(),
-    )));
-    
-    let result = solve(entities, timesteps);
+    entities.push(Entity::Producer(
+        Producer::new(
+            vec![0.0],
+            vec![1.0],
+            vec![0.0, 1.0],
+            true,
+            "producer".to_string(),
+        )
+        .unwrap(),
+    ));
+    entities.push(Entity::Consumer(
+        Consumer::new(vec![0.0], vec![1.0], vec![0.0, 1.0], "consumer".to_string()).unwrap(),
+    ));
+
+    let result = solve(&entities, timesteps);
     assert_eq!(result.is_ok(), true);
 
     let mut unwrapped_result = result.unwrap();
 
     let consumer = match unwrapped_result.pop().unwrap() {
-        Entity::Consumer(consumer) => consumer,
+        EntityResult::Consumer(consumer) => consumer,
         _ => panic!("Expected Consumer"),
     };
 
     let producer = match unwrapped_result.pop().unwrap() {
-        Entity::Producer(producer) => producer,
+        EntityResult::Producer(producer) => producer,
         _ => panic!("Expected Producer"),
     };
 
     assert_eq!(consumer.consumed, vec![0.0, 1.0, 0.0, 1.0]);
     assert_eq!(producer.produced, vec![0.0, 1.0, 0.0, 1.0]);
-
 }
 
 #[test]
 fn storage_to_grid_allowed() {
-
     let mut entities: Vec<Entity> = vec![];
     let timesteps = 4;
 
-    entities.push(Entity::Grid(Grid::new(
-        vec![-1.0],
-        vec![1.0],
-        vec![0.0],
-        vec![1.0],
-        "grid".to_string(),
-    )));
-    entities.push(Entity::Storage(Storage::new(
-        vec![0.0],
-        vec![1.0],
-        vec![1.0],
-        vec![0.0],
-        vec![1.0],
-        vec![1.0],
-        20.0,
-        20.0,
-        None,
-        true,
-        false,
-        "storage".to_string(),
-    )));
-    
-    let result = solve(entities, timesteps);
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![-1.0],
+            vec![1.0],
+            vec![0.0],
+            vec![1.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+    entities.push(Entity::Storage(
+        Storage::new(
+            vec![0.0],
+            vec![1.0],
+            vec![1.0],
+            vec![0.0],
+            vec![1.0],
+            vec![1.0],
+            20.0,
+            20.0,
+            None,
+            true,
+            false,
+            "storage".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let result = solve(&entities, timesteps);
     assert_eq!(result.is_ok(), true);
 
     let mut unwrapped_result = result.unwrap();
 
     let storage = match unwrapped_result.pop().unwrap() {
-        Entity::Storage(storage) => storage,
+        EntityResult::Storage(storage) => storage,
         _ => panic!("Expected Storage"),
     };
 
     let grid = match unwrapped_result.pop().unwrap() {
-        Entity::Grid(grid) => grid,
+        EntityResult::Grid(grid) => grid,
         _ => panic!("Expected Grid"),
     };
 
@@ -230,55 +253,2763 @@ fn storage_to_grid_allowed() {
 
     assert_eq!(storage.produced, vec![1.0, 1.0, 1.0, 1.0]);
     assert_eq!(grid.consumed, vec![1.0, 1.0, 1.0, 1.0]);
-
 }
 
 #[test]
 fn storage_to_grid_not_allowed() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 4;
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![-1.0],
+            vec![1.0],
+            vec![0.0],
+            vec![1.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+    entities.push(Entity::Storage(
+        Storage::new(
+            vec![0.0],
+            vec![1.0],
+            vec![1.0],
+            vec![0.0],
+            vec![1.0],
+            vec![1.0],
+            20.0,
+            20.0,
+            None,
+            false,
+            false,
+            "storage".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let result = solve(&entities, timesteps);
+    assert_eq!(result.is_ok(), true);
+
+    let mut unwrapped_result = result.unwrap();
+
+    let storage = match unwrapped_result.pop().unwrap() {
+        EntityResult::Storage(storage) => storage,
+        _ => panic!("Expected Storage"),
+    };
+
+    let grid = match unwrapped_result.pop().unwrap() {
+        EntityResult::Grid(grid) => grid,
+        _ => panic!("Expected Grid"),
+    };
+
+    println!("storage: {:?}", storage.stored);
+
+    assert_eq!(storage.produced, vec![0.0, 0.0, 0.0, 0.0]);
+    assert_eq!(grid.consumed, vec![0.0, 0.0, 0.0, 0.0]);
+}
+
+#[test]
+fn line_with_losses() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 4;
+
+    let mut grid = Grid::new(
+        vec![0.0],
+        vec![0.0],
+        vec![1.0],
+        vec![2.0],
+        "grid".to_string(),
+    )
+    .unwrap();
+    grid.bus = "a".to_string();
+    entities.push(Entity::Grid(grid));
+
+    entities.push(Entity::Line(
+        Line::new(
+            vec![0.0],
+            vec![0.5],
+            vec![2.0],
+            vec![2.0],
+            "a".to_string(),
+            "b".to_string(),
+            "line".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let mut consumer =
+        Consumer::new(vec![0.0], vec![1.0], vec![1.0], "consumer".to_string()).unwrap();
+    consumer.bus = "b".to_string();
+    entities.push(Entity::Consumer(consumer));
 
+    let result = solve(&entities, timesteps);
+    assert!(result.is_ok());
+
+    let mut unwrapped_result = result.unwrap();
+
+    let consumer = match unwrapped_result.pop().unwrap() {
+        EntityResult::Consumer(consumer) => consumer,
+        _ => panic!("Expected Consumer"),
+    };
+
+    let line = match unwrapped_result.pop().unwrap() {
+        EntityResult::Line(line) => line,
+        _ => panic!("Expected Line"),
+    };
+
+    let grid = match unwrapped_result.pop().unwrap() {
+        EntityResult::Grid(grid) => grid,
+        _ => panic!("Expected Grid"),
+    };
+
+    assert_eq!(consumer.consumed, vec![1.0, 1.0, 1.0, 1.0]);
+    assert_eq!(line.flow, vec![2.0, 2.0, 2.0, 2.0]);
+    assert_eq!(grid.produced, vec![2.0, 2.0, 2.0, 2.0]);
+}
+
+#[test]
+fn line_capacity_limit() {
     let mut entities: Vec<Entity> = vec![];
     let timesteps = 4;
 
-    entities.push(Entity::Grid(Grid::new(
-        vec![-1.0],
+    let mut grid = Grid::new(
+        vec![0.0],
+        vec![0.0],
+        vec![1.0],
+        vec![2.0],
+        "grid".to_string(),
+    )
+    .unwrap();
+    grid.bus = "a".to_string();
+    entities.push(Entity::Grid(grid));
+
+    entities.push(Entity::Line(
+        Line::new(
+            vec![0.0],
+            vec![1.0],
+            vec![1.0],
+            vec![1.0],
+            "a".to_string(),
+            "b".to_string(),
+            "line".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let mut producer = Producer::new(
+        vec![2.0],
         vec![1.0],
+        vec![1.0],
+        true,
+        "producer".to_string(),
+    )
+    .unwrap();
+    producer.bus = "b".to_string();
+    entities.push(Entity::Producer(producer));
+
+    let mut consumer =
+        Consumer::new(vec![0.0], vec![1.0], vec![2.0], "consumer".to_string()).unwrap();
+    consumer.bus = "b".to_string();
+    entities.push(Entity::Consumer(consumer));
+
+    let result = solve(&entities, timesteps);
+    assert!(result.is_ok());
+
+    let mut unwrapped_result = result.unwrap();
+
+    unwrapped_result.pop();
+
+    let producer = match unwrapped_result.pop().unwrap() {
+        EntityResult::Producer(producer) => producer,
+        _ => panic!("Expected Producer"),
+    };
+
+    let line = match unwrapped_result.pop().unwrap() {
+        EntityResult::Line(line) => line,
+        _ => panic!("Expected Line"),
+    };
+
+    assert_eq!(line.flow, vec![1.0, 1.0, 1.0, 1.0]);
+    assert_eq!(producer.produced, vec![1.0, 1.0, 1.0, 1.0]);
+}
+
+#[test]
+fn dc_power_flow_meshed() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 2;
+
+    let mut grid = Grid::new(
+        vec![0.0],
         vec![0.0],
         vec![1.0],
+        vec![3.0],
         "grid".to_string(),
-    )));
-    entities.push(Entity::Storage(Storage::new(
+    )
+    .unwrap();
+    grid.bus = "a".to_string();
+    entities.push(Entity::Grid(grid));
+
+    for (from, to) in [("a", "b"), ("b", "c"), ("a", "c")] {
+        let mut line = Line::new(
+            vec![0.0],
+            vec![1.0],
+            vec![4.0],
+            vec![4.0],
+            from.to_string(),
+            to.to_string(),
+            format!("{}-{}", from, to),
+        )
+        .unwrap();
+        line.reactance = Some(1.0);
+        entities.push(Entity::Line(line));
+    }
+
+    let mut consumer =
+        Consumer::new(vec![0.0], vec![1.0], vec![3.0], "consumer".to_string()).unwrap();
+    consumer.bus = "c".to_string();
+    entities.push(Entity::Consumer(consumer));
+
+    let options = SolveOptions {
+        dc_power_flow: true,
+        slack_bus: Some("a".to_string()),
+        ..Default::default()
+    };
+
+    let result = solve_with_options(&entities, timesteps, &options);
+    assert!(result.is_ok());
+
+    let mut unwrapped_result = result.unwrap().entities;
+
+    unwrapped_result.pop();
+
+    let lines: Vec<LineResult> = unwrapped_result
+        .drain(1..)
+        .map(|entity| match entity {
+            EntityResult::Line(line) => line,
+            _ => panic!("Expected Line"),
+        })
+        .collect();
+
+    assert_eq!(lines[0].flow, vec![1.0, 1.0]);
+    assert_eq!(lines[1].flow, vec![1.0, 1.0]);
+    assert_eq!(lines[2].flow, vec![2.0, 2.0]);
+
+    assert_eq!(lines[0].loading, vec![25.0, 25.0]);
+    assert_eq!(lines[2].loading, vec![50.0, 50.0]);
+}
+
+#[test]
+fn dc_power_flow_unknown_slack_bus() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 2;
+
+    let mut line = Line::new(
+        vec![0.0],
+        vec![1.0],
+        vec![1.0],
+        vec![1.0],
+        "a".to_string(),
+        "b".to_string(),
+        "line".to_string(),
+    )
+    .unwrap();
+    line.reactance = Some(1.0);
+    entities.push(Entity::Line(line));
+
+    let options = SolveOptions {
+        dc_power_flow: true,
+        slack_bus: Some("c".to_string()),
+        ..Default::default()
+    };
+
+    let result = solve_with_options(&entities, timesteps, &options);
+    assert!(result.is_err());
+}
+
+#[test]
+fn dc_power_flow_slack_bus_without_reactance() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 2;
+
+    let mut line = Line::new(
         vec![0.0],
         vec![1.0],
         vec![1.0],
+        vec![1.0],
+        "a".to_string(),
+        "b".to_string(),
+        "line".to_string(),
+    )
+    .unwrap();
+    line.reactance = Some(1.0);
+    entities.push(Entity::Line(line));
+
+    // Bus c exists, but is not connected by a line with a reactance
+    let mut grid = Grid::new(
+        vec![0.0],
         vec![0.0],
         vec![1.0],
         vec![1.0],
-        20.0,
-        20.0,
-        None,
-        false,
-        false,
-        "storage".to_string(),
-    )));
-    
-    let result = solve(entities, timesteps);
-    assert_eq!(result.is_ok(), true);
+        "grid".to_string(),
+    )
+    .unwrap();
+    grid.bus = "c".to_string();
+    entities.push(Entity::Grid(grid));
 
-    let mut unwrapped_result = result.unwrap();
+    let options = SolveOptions {
+        dc_power_flow: true,
+        slack_bus: Some("c".to_string()),
+        ..Default::default()
+    };
 
-    let storage = match unwrapped_result.pop().unwrap() {
-        Entity::Storage(storage) => storage,
+    let result = solve_with_options(&entities, timesteps, &options);
+    assert!(matches!(result, Err(Error::Invalid { .. })));
+}
+
+#[test]
+fn nodal_prices_with_congestion() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 2;
+
+    let mut grid = Grid::new(
+        vec![0.0],
+        vec![0.0],
+        vec![1.0, 3.0],
+        vec![2.0],
+        "grid".to_string(),
+    )
+    .unwrap();
+    grid.bus = "a".to_string();
+    entities.push(Entity::Grid(grid));
+
+    entities.push(Entity::Line(
+        Line::new(
+            vec![0.0],
+            vec![1.0],
+            vec![1.0],
+            vec![1.0],
+            "a".to_string(),
+            "b".to_string(),
+            "line".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let mut producer = Producer::new(
+        vec![2.0],
+        vec![1.0],
+        vec![4.0],
+        true,
+        "producer".to_string(),
+    )
+    .unwrap();
+    producer.bus = "b".to_string();
+    entities.push(Entity::Producer(producer));
+
+    let mut consumer =
+        Consumer::new(vec![0.0], vec![1.0], vec![2.0], "consumer".to_string()).unwrap();
+    consumer.bus = "b".to_string();
+    entities.push(Entity::Consumer(consumer));
+
+    let result = solve_with_options(
+        &entities,
+        timesteps,
+        &SolveOptions {
+            prices: true,
+            ..Default::default()
+        },
+    );
+    assert!(result.is_ok());
+
+    let result = result.unwrap();
+
+    assert_eq!(result.prices["a"], vec![1.0, 2.0]);
+    assert_eq!(result.prices["b"], vec![2.0, 2.0]);
+
+    let producer = match &result.entities[2] {
+        EntityResult::Producer(producer) => producer,
+        _ => panic!("Expected Producer"),
+    };
+
+    assert_eq!(producer.price, vec![2.0, 2.0]);
+    assert_eq!(producer.produced, vec![1.0, 2.0]);
+}
+
+#[test]
+fn storage_capacity_value() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 2;
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![0.0],
+            vec![1.0, 3.0],
+            vec![2.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+    entities.push(Entity::Storage(
+        Storage::new(
+            vec![0.0],
+            vec![1.0],
+            vec![2.0],
+            vec![0.0],
+            vec![1.0],
+            vec![2.0],
+            1.0,
+            0.0,
+            None,
+            false,
+            true,
+            "storage".to_string(),
+        )
+        .unwrap(),
+    ));
+    entities.push(Entity::Consumer(
+        Consumer::new(vec![0.0], vec![1.0], vec![0.0, 2.0], "consumer".to_string()).unwrap(),
+    ));
+
+    let result = solve_with_options(
+        &entities,
+        timesteps,
+        &SolveOptions {
+            prices: true,
+            ..Default::default()
+        },
+    );
+    assert!(result.is_ok());
+
+    let mut result = result.unwrap();
+
+    assert_eq!(result.prices["main"], vec![1.0, 3.0]);
+
+    result.entities.pop();
+
+    let storage = match result.entities.pop().unwrap() {
+        EntityResult::Storage(storage) => storage,
         _ => panic!("Expected Storage"),
     };
 
+    assert_eq!(storage.stored, vec![1.0, 0.0]);
+    assert_eq!(storage.capacity_value, 2.0);
+}
+
+#[test]
+fn producer_min_load() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 2;
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![10.0],
+            vec![5.0],
+            vec![10.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let mut producer = Producer::new(
+        vec![1.0],
+        vec![1.0],
+        vec![4.0],
+        true,
+        "producer".to_string(),
+    )
+    .unwrap();
+    producer.min_load = 0.5;
+    entities.push(Entity::Producer(producer));
+
+    entities.push(Entity::Consumer(
+        Consumer::new(vec![0.0], vec![1.0], vec![1.0, 3.0], "consumer".to_string()).unwrap(),
+    ));
+
+    let result = solve(&entities, timesteps);
+    assert!(result.is_ok());
+
+    let mut unwrapped_result = result.unwrap();
+
+    unwrapped_result.pop();
+
+    let producer = match unwrapped_result.pop().unwrap() {
+        EntityResult::Producer(producer) => producer,
+        _ => panic!("Expected Producer"),
+    };
+
     let grid = match unwrapped_result.pop().unwrap() {
-        Entity::Grid(grid) => grid,
+        EntityResult::Grid(grid) => grid,
         _ => panic!("Expected Grid"),
     };
 
-    println!("storage: {:?}", storage.stored);
+    assert_eq!(producer.produced, vec![2.0, 3.0]);
+    assert_eq!(producer.committed, vec![true, true]);
+    assert_eq!(grid.consumed, vec![1.0, 0.0]);
+}
 
-    assert_eq!(storage.produced, vec![0.0, 0.0, 0.0, 0.0]);
-    assert_eq!(grid.consumed, vec![0.0, 0.0, 0.0, 0.0]);
+#[test]
+fn producer_startup_cost_and_min_up_time() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 5;
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![10.0],
+            vec![3.0],
+            vec![10.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let mut producer = Producer::new(
+        vec![1.0],
+        vec![1.0],
+        vec![1.0],
+        true,
+        "producer".to_string(),
+    )
+    .unwrap();
+    producer.min_load = 0.5;
+    producer.startup_cost = 1.5;
+    producer.min_up_time = 3;
+    entities.push(Entity::Producer(producer));
+
+    entities.push(Entity::Consumer(
+        Consumer::new(
+            vec![0.0],
+            vec![1.0],
+            vec![1.0, 0.0, 0.0, 0.0, 1.0],
+            "consumer".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let result = solve(&entities, timesteps);
+    assert!(result.is_ok());
+
+    let mut unwrapped_result = result.unwrap();
 
-}
\ No newline at end of file
+    unwrapped_result.pop();
+
+    let producer = match unwrapped_result.pop().unwrap() {
+        EntityResult::Producer(producer) => producer,
+        _ => panic!("Expected Producer"),
+    };
+
+    // Staying on at minimum load is cheaper than a second start
+    assert_eq!(producer.produced, vec![1.0, 0.5, 0.5, 0.5, 1.0]);
+    assert_eq!(producer.committed, vec![true, true, true, true, true]);
+}
+
+#[test]
+fn producer_ramp_up() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 3;
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![0.0],
+            vec![1.0],
+            vec![10.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let mut producer = Producer::new(
+        vec![0.0],
+        vec![1.0],
+        vec![4.0],
+        true,
+        "producer".to_string(),
+    )
+    .unwrap();
+    producer.ramp_up = vec![2.0].into();
+    entities.push(Entity::Producer(producer));
+
+    entities.push(Entity::Consumer(
+        Consumer::new(
+            vec![0.0],
+            vec![1.0],
+            vec![0.0, 4.0, 4.0],
+            "consumer".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let result = solve(&entities, timesteps);
+    assert!(result.is_ok());
+
+    let mut unwrapped_result = result.unwrap();
+
+    unwrapped_result.pop();
+
+    let producer = match unwrapped_result.pop().unwrap() {
+        EntityResult::Producer(producer) => producer,
+        _ => panic!("Expected Producer"),
+    };
+
+    let grid = match unwrapped_result.pop().unwrap() {
+        EntityResult::Grid(grid) => grid,
+        _ => panic!("Expected Grid"),
+    };
+
+    assert_eq!(producer.produced, vec![0.0, 2.0, 4.0]);
+    assert_eq!(producer.ramp_binding, vec![false, true, true]);
+    assert_eq!(grid.produced, vec![0.0, 2.0, 0.0]);
+}
+
+#[test]
+fn grid_ramp_up() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 3;
+
+    let mut grid = Grid::new(
+        vec![0.0],
+        vec![0.0],
+        vec![1.0],
+        vec![10.0],
+        "grid".to_string(),
+    )
+    .unwrap();
+    grid.ramp_up = vec![1.0].into();
+    entities.push(Entity::Grid(grid));
+
+    entities.push(Entity::Producer(
+        Producer::new(
+            vec![5.0],
+            vec![1.0],
+            vec![1.0],
+            true,
+            "producer".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    entities.push(Entity::Consumer(
+        Consumer::new(
+            vec![0.0],
+            vec![1.0],
+            vec![0.0, 2.0, 2.0],
+            "consumer".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let result = solve(&entities, timesteps);
+    assert!(result.is_ok());
+
+    let mut unwrapped_result = result.unwrap();
+
+    unwrapped_result.pop();
+
+    let producer = match unwrapped_result.pop().unwrap() {
+        EntityResult::Producer(producer) => producer,
+        _ => panic!("Expected Producer"),
+    };
+
+    let grid = match unwrapped_result.pop().unwrap() {
+        EntityResult::Grid(grid) => grid,
+        _ => panic!("Expected Grid"),
+    };
+
+    assert_eq!(grid.produced, vec![0.0, 1.0, 2.0]);
+    assert_eq!(grid.ramp_binding, vec![false, true, true]);
+    assert_eq!(producer.produced, vec![0.0, 1.0, 0.0]);
+}
+
+#[test]
+fn storage_self_discharge() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 3;
+
+    let mut storage = Storage::new(
+        vec![0.0],
+        vec![1.0],
+        vec![1.0],
+        vec![0.0],
+        vec![1.0],
+        vec![1.0],
+        10.0,
+        10.0,
+        None,
+        false,
+        false,
+        "storage".to_string(),
+    )
+    .unwrap();
+    storage.self_discharge = vec![0.5].into();
+    entities.push(Entity::Storage(storage));
+
+    entities.push(Entity::Consumer(
+        Consumer::new(
+            vec![0.0],
+            vec![1.0],
+            vec![0.0, 0.0, 1.0],
+            "consumer".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let result = solve(&entities, timesteps);
+    assert!(result.is_ok());
+
+    let mut unwrapped_result = result.unwrap();
+
+    unwrapped_result.pop();
+
+    let storage = match unwrapped_result.pop().unwrap() {
+        EntityResult::Storage(storage) => storage,
+        _ => panic!("Expected Storage"),
+    };
+
+    assert_eq!(storage.produced, vec![0.0, 0.0, 1.0]);
+    assert_eq!(storage.stored, vec![5.0, 2.5, 0.25]);
+}
+
+// A MIP with a year of hourly timesteps, run with `cargo test -- --ignored`
+#[test]
+#[ignore]
+fn storage_year_horizon() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 8760;
+
+    let solar: Vec<f64> = (0..24)
+        .map(|hour| if (6..18).contains(&hour) { 2.0 } else { 0.0 })
+        .collect();
+
+    entities.push(Entity::Producer(
+        Producer::new(vec![0.0], vec![1.0], solar, true, "solar".to_string()).unwrap(),
+    ));
+
+    entities.push(Entity::Storage(
+        Storage::new(
+            vec![0.0],
+            vec![1.0],
+            vec![1.0],
+            vec![0.0],
+            vec![1.0],
+            vec![1.0],
+            12.0,
+            0.0,
+            None,
+            false,
+            false,
+            "storage".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![10.0],
+            vec![1.0],
+            vec![10.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    entities.push(Entity::Consumer(
+        Consumer::new(vec![0.0], vec![1.0], vec![1.0], "consumer".to_string()).unwrap(),
+    ));
+
+    let result = solve(&entities, timesteps);
+    assert!(result.is_ok());
+
+    let mut unwrapped_result = result.unwrap();
+
+    unwrapped_result.pop();
+    let grid = match unwrapped_result.pop().unwrap() {
+        EntityResult::Grid(grid) => grid,
+        _ => panic!("Expected Grid"),
+    };
+
+    // the storage shifts the solar surplus into the night, only the first night needs the grid
+    assert_float_eq!(grid.produced.iter().sum::<f64>(), 6.0, abs <= 1e-6);
+}
+
+#[test]
+fn grid_peak_cost() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 4;
+
+    let mut grid = Grid::new(
+        vec![0.0],
+        vec![4.0],
+        vec![1.0, 1.0, 2.0, 2.0],
+        vec![4.0],
+        "grid".to_string(),
+    )
+    .unwrap();
+    grid.peak_cost = 10.0;
+    grid.peak_window = Some(2);
+    entities.push(Entity::Grid(grid));
+
+    entities.push(Entity::Storage(
+        Storage::new(
+            vec![0.0],
+            vec![1.0],
+            vec![2.0],
+            vec![0.0],
+            vec![1.0],
+            vec![2.0],
+            4.0,
+            0.0,
+            None,
+            false,
+            true,
+            "storage".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    entities.push(Entity::Consumer(
+        Consumer::new(
+            vec![0.0],
+            vec![1.0],
+            vec![0.0, 2.0, 0.0, 4.0],
+            "consumer".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let result = solve(&entities, timesteps);
+    assert!(result.is_ok());
+
+    let mut unwrapped_result = result.unwrap();
+
+    unwrapped_result.pop();
+    unwrapped_result.pop();
+
+    let grid = match unwrapped_result.pop().unwrap() {
+        EntityResult::Grid(grid) => grid,
+        _ => panic!("Expected Grid"),
+    };
+
+    assert_eq!(grid.produced, vec![1.0, 1.0, 2.0, 2.0]);
+    assert_eq!(grid.peak, vec![1.0, 2.0]);
+    assert_eq!(grid.peak_charge, 30.0);
+    assert_eq!(grid.energy_cost, 10.0);
+}
+
+#[test]
+fn connection_point_export_limit() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 2;
+
+    entities.push(Entity::Producer(
+        Producer::new(vec![0.0], vec![1.0], vec![10.0], true, "pv".to_string()).unwrap(),
+    ));
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![-1.0],
+            vec![8.0],
+            vec![2.0],
+            vec![10.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    entities.push(Entity::Consumer(
+        Consumer::new(vec![0.0], vec![1.0], vec![1.0, 4.0], "consumer".to_string()).unwrap(),
+    ));
+
+    // feed-in is capped at 70% of the PV peak
+    entities.push(Entity::ConnectionPoint(
+        ConnectionPoint::new(
+            vec![],
+            vec![7.0],
+            vec![],
+            vec!["grid".to_string()],
+            "pcc".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let result = solve(&entities, timesteps);
+    assert!(result.is_ok());
+
+    let mut unwrapped_result = result.unwrap();
+
+    let connection_point = match unwrapped_result.pop().unwrap() {
+        EntityResult::ConnectionPoint(connection_point) => connection_point,
+        _ => panic!("Expected ConnectionPoint"),
+    };
+    unwrapped_result.pop();
+    let grid = match unwrapped_result.pop().unwrap() {
+        EntityResult::Grid(grid) => grid,
+        _ => panic!("Expected Grid"),
+    };
+    let pv = match unwrapped_result.pop().unwrap() {
+        EntityResult::Producer(producer) => producer,
+        _ => panic!("Expected Producer"),
+    };
+
+    assert_eq!(grid.consumed, vec![7.0, 6.0]);
+    assert_eq!(pv.produced, vec![8.0, 10.0]);
+    assert_eq!(connection_point.net_import, vec![-7.0, -6.0]);
+}
+
+#[test]
+fn connection_point_infeasible() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 1;
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![10.0],
+            vec![1.0],
+            vec![10.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    entities.push(Entity::Consumer(
+        Consumer::new(vec![0.0], vec![1.0], vec![5.0], "consumer".to_string()).unwrap(),
+    ));
+
+    entities.push(Entity::ConnectionPoint(
+        ConnectionPoint::new(
+            vec![],
+            vec![],
+            vec![4.0],
+            vec!["grid".to_string()],
+            "transformer".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    // A connection point whose limits are not binding is not named
+    entities.push(Entity::ConnectionPoint(
+        ConnectionPoint::new(
+            vec![10.0],
+            vec![],
+            vec![],
+            vec!["grid".to_string()],
+            "feeder".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let result = solve(&entities, timesteps);
+
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "The limits of connection point transformer make the problem infeasible"
+    );
+}
+
+#[test]
+fn carbon_price_and_emission_cap() {
+    let build_entities = || {
+        let mut coal =
+            Producer::new(vec![1.0], vec![1.0], vec![4.0], true, "coal".to_string()).unwrap();
+        coal.emission_factor = vec![1.0].into();
+
+        let mut grid = Grid::new(
+            vec![0.0],
+            vec![0.0],
+            vec![2.0],
+            vec![4.0],
+            "grid".to_string(),
+        )
+        .unwrap();
+        grid.emission_factor = vec![0.5].into();
+
+        vec![
+            Entity::Producer(coal),
+            Entity::Grid(grid),
+            Entity::Consumer(
+                Consumer::new(vec![0.0], vec![1.0], vec![2.0], "consumer".to_string()).unwrap(),
+            ),
+        ]
+    };
+    let timesteps = 2;
+
+    // without a carbon price the cheap coal plant supplies everything
+    let result =
+        solve_with_options(&build_entities(), timesteps, &SolveOptions::default()).unwrap();
+    assert_eq!(result.emissions, 4.0);
+
+    // the carbon price makes the import cheaper
+    let options = SolveOptions {
+        carbon_price: 4.0,
+        ..Default::default()
+    };
+    let result = solve_with_options(&build_entities(), timesteps, &options).unwrap();
+    assert_eq!(result.emissions, 2.0);
+
+    let grid = match &result.entities[1] {
+        EntityResult::Grid(grid) => grid,
+        _ => panic!("Expected Grid"),
+    };
+    assert_eq!(grid.emissions, vec![1.0, 1.0]);
+
+    // the cap forces a partial switch
+    let options = SolveOptions {
+        emission_cap: Some(3.0),
+        ..Default::default()
+    };
+    let result = solve_with_options(&build_entities(), timesteps, &options).unwrap();
+    assert_eq!(result.emissions, 3.0);
+
+    let coal = match &result.entities[0] {
+        EntityResult::Producer(producer) => producer,
+        _ => panic!("Expected Producer"),
+    };
+    assert_eq!(coal.emissions.iter().sum::<f64>(), 2.0);
+}
+
+#[test]
+fn flexible_consumer() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 6;
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![0.0],
+            vec![3.0, 1.0, 2.0, 2.0, 1.5, 1.0],
+            vec![10.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    // 3 units of energy between timestep 1 and 4 at no more than 2 per timestep
+    let mut consumer =
+        Consumer::new(vec![0.0], vec![1.0], vec![2.0], "dishwasher".to_string()).unwrap();
+    consumer.energy = Some(3.0);
+    consumer.earliest_start = 1;
+    consumer.latest_end = Some(5);
+    entities.push(Entity::Consumer(consumer));
+
+    let result = solve(&entities, timesteps);
+    assert!(result.is_ok());
+
+    let mut unwrapped_result = result.unwrap();
+
+    let consumer = match unwrapped_result.pop().unwrap() {
+        EntityResult::Consumer(consumer) => consumer,
+        _ => panic!("Expected Consumer"),
+    };
+
+    assert_eq!(consumer.consumed, vec![0.0, 2.0, 0.0, 0.0, 1.0, 0.0]);
+}
+
+#[test]
+fn consumer_value_of_lost_load() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 2;
+
+    entities.push(Entity::Producer(
+        Producer::new(vec![1.0], vec![1.0], vec![2.0], true, "diesel".to_string()).unwrap(),
+    ));
+
+    let mut consumer =
+        Consumer::new(vec![0.0], vec![1.0], vec![1.0, 3.0], "island".to_string()).unwrap();
+    consumer.value_of_lost_load = Some(100.0);
+    entities.push(Entity::Consumer(consumer));
+
+    let result = solve_with_options(
+        &entities,
+        timesteps,
+        &SolveOptions {
+            prices: true,
+            ..Default::default()
+        },
+    );
+    assert!(result.is_ok());
+
+    let mut unwrapped_result = result.unwrap().entities;
+
+    let consumer = match unwrapped_result.pop().unwrap() {
+        EntityResult::Consumer(consumer) => consumer,
+        _ => panic!("Expected Consumer"),
+    };
+
+    assert_eq!(consumer.consumed, vec![1.0, 2.0]);
+    assert_eq!(consumer.unserved, vec![0.0, 1.0]);
+    assert_eq!(consumer.price, vec![1.0, 100.0]);
+}
+
+#[test]
+fn flexible_consumer_value_of_lost_load() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 2;
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![0.0],
+            vec![1.0],
+            vec![10.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    // More energy than 1 unit of power can take within 2 timesteps
+    let mut consumer =
+        Consumer::new(vec![0.0], vec![1.0], vec![1.0], "dishwasher".to_string()).unwrap();
+    consumer.energy = Some(3.0);
+    consumer.value_of_lost_load = Some(100.0);
+    entities.push(Entity::Consumer(consumer));
+
+    let result = solve(&entities, timesteps);
+    assert!(result.is_ok());
+
+    let mut unwrapped_result = result.unwrap();
+
+    let consumer = match unwrapped_result.pop().unwrap() {
+        EntityResult::Consumer(consumer) => consumer,
+        _ => panic!("Expected Consumer"),
+    };
+
+    assert_float_eq!(consumer.consumed, vec![1.0, 1.0], abs_all <= 1e-6);
+    assert_float_eq!(consumer.unserved, vec![0.0, 0.0], abs_all <= 1e-6);
+    assert_float_eq!(consumer.excess, 1.0, abs <= 1e-6);
+}
+
+#[test]
+fn electric_vehicle_departure_soc() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 6;
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![0.0],
+            vec![3.0, 1.0, 2.0, 3.0, 3.0, 1.0],
+            vec![10.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    // plugged in until timestep 3, drives 2 per timestep and returns in timestep 5
+    entities.push(Entity::ElectricVehicle(
+        ElectricVehicle::new(
+            vec![2.0],
+            vec![1.0],
+            10.0,
+            1.0,
+            vec![true, true, true, false, false, true],
+            vec![2.0],
+            vec![5.0],
+            "car".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let result = solve(&entities, timesteps);
+    assert!(result.is_ok());
+
+    let mut unwrapped_result = result.unwrap();
+
+    let electric_vehicle = match unwrapped_result.pop().unwrap() {
+        EntityResult::ElectricVehicle(electric_vehicle) => electric_vehicle,
+        _ => panic!("Expected ElectricVehicle"),
+    };
+
+    assert_eq!(electric_vehicle.charged, vec![0.0, 2.0, 2.0, 0.0, 0.0, 0.0]);
+    assert_eq!(electric_vehicle.stored, vec![1.0, 3.0, 5.0, 3.0, 1.0, 1.0]);
+    assert_eq!(electric_vehicle.shortfall, vec![0.0; 6]);
+}
+
+#[test]
+fn electric_vehicle_shortfall() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 3;
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![0.0],
+            vec![1.0],
+            vec![10.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let mut electric_vehicle = ElectricVehicle::new(
+        vec![1.0],
+        vec![1.0],
+        10.0,
+        0.0,
+        vec![true, true, false],
+        vec![1.0],
+        vec![4.0],
+        "car".to_string(),
+    )
+    .unwrap();
+    electric_vehicle.shortfall_cost = Some(100.0);
+    entities.push(Entity::ElectricVehicle(electric_vehicle));
+
+    let result = solve(&entities, timesteps);
+    assert!(result.is_ok());
+
+    let mut unwrapped_result = result.unwrap();
+
+    let electric_vehicle = match unwrapped_result.pop().unwrap() {
+        EntityResult::ElectricVehicle(electric_vehicle) => electric_vehicle,
+        _ => panic!("Expected ElectricVehicle"),
+    };
+
+    assert_eq!(electric_vehicle.charged, vec![1.0, 1.0, 0.0]);
+    assert_eq!(electric_vehicle.shortfall, vec![0.0, 0.0, 2.0]);
+}
+
+#[test]
+fn heat_pump_with_cop() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 2;
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![0.0],
+            vec![1.0],
+            vec![10.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    entities.push(Entity::Converter(
+        Converter::new(
+            vec![0.0],
+            vec![4.0],
+            vec![ConverterPort::new("electricity".to_string(), vec![1.0]).unwrap()],
+            vec![ConverterPort::new("heat".to_string(), vec![2.0, 4.0]).unwrap()],
+            "heat pump".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let mut consumer =
+        Consumer::new(vec![0.0], vec![1.0], vec![4.0], "building".to_string()).unwrap();
+    consumer.carrier = "heat".to_string();
+    entities.push(Entity::Consumer(consumer));
+
+    let result = solve_with_options(
+        &entities,
+        timesteps,
+        &SolveOptions {
+            prices: true,
+            ..Default::default()
+        },
+    );
+    assert!(result.is_ok());
+
+    let result = result.unwrap();
+
+    let grid = match &result.entities[0] {
+        EntityResult::Grid(grid) => grid,
+        _ => panic!("Expected Grid"),
+    };
+    let heat_pump = match &result.entities[1] {
+        EntityResult::Converter(converter) => converter,
+        _ => panic!("Expected Converter"),
+    };
+
+    assert_eq!(grid.produced, vec![2.0, 1.0]);
+    assert_eq!(heat_pump.produced["heat"], vec![4.0, 4.0]);
+    assert_eq!(result.prices["main:heat"], vec![0.5, 0.25]);
+}
+
+#[test]
+fn heat_pump_supplied_by_storage() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 4;
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![0.0],
+            vec![1.0],
+            vec![10.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    // Cannot feed the grid, but may supply the heat pump on site
+    entities.push(Entity::Storage(
+        Storage::new(
+            vec![0.0],
+            vec![1.0],
+            vec![1.0],
+            vec![0.0],
+            vec![1.0],
+            vec![1.0],
+            20.0,
+            20.0,
+            None,
+            false,
+            false,
+            "storage".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    entities.push(Entity::Converter(
+        Converter::new(
+            vec![0.0],
+            vec![1.0],
+            vec![ConverterPort::new("electricity".to_string(), vec![1.0]).unwrap()],
+            vec![ConverterPort::new("heat".to_string(), vec![1.0]).unwrap()],
+            "heat pump".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let mut consumer =
+        Consumer::new(vec![0.0], vec![1.0], vec![1.0], "building".to_string()).unwrap();
+    consumer.carrier = "heat".to_string();
+    entities.push(Entity::Consumer(consumer));
+
+    let result = solve(&entities, timesteps);
+    assert!(result.is_ok());
+
+    let result = result.unwrap();
+
+    let grid = match &result[0] {
+        EntityResult::Grid(grid) => grid,
+        _ => panic!("Expected Grid"),
+    };
+    let storage = match &result[1] {
+        EntityResult::Storage(storage) => storage,
+        _ => panic!("Expected Storage"),
+    };
+
+    assert_float_eq!(storage.produced, vec![1.0, 1.0, 1.0, 1.0], abs_all <= 1e-6);
+    assert_float_eq!(grid.produced, vec![0.0, 0.0, 0.0, 0.0], abs_all <= 1e-6);
+}
+
+#[test]
+fn converter_ports_with_same_carrier() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 2;
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![0.0],
+            vec![1.0],
+            vec![10.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    entities.push(Entity::Converter(
+        Converter::new(
+            vec![0.0],
+            vec![1.0],
+            vec![ConverterPort::new("electricity".to_string(), vec![1.0]).unwrap()],
+            vec![
+                ConverterPort::new("heat".to_string(), vec![1.0]).unwrap(),
+                ConverterPort::new("heat".to_string(), vec![2.0]).unwrap(),
+            ],
+            "heat pump".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let mut consumer =
+        Consumer::new(vec![0.0], vec![1.0], vec![3.0], "building".to_string()).unwrap();
+    consumer.carrier = "heat".to_string();
+    entities.push(Entity::Consumer(consumer));
+
+    let result = solve(&entities, timesteps).unwrap();
+
+    let heat_pump = match &result[1] {
+        EntityResult::Converter(converter) => converter,
+        _ => panic!("Expected Converter"),
+    };
+
+    assert_eq!(heat_pump.produced["heat"], vec![3.0, 3.0]);
+}
+
+#[test]
+fn chp_with_fixed_power_to_heat_ratio() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 1;
+
+    let mut gas = Grid::new(
+        vec![0.0],
+        vec![0.0],
+        vec![1.0],
+        vec![100.0],
+        "gas".to_string(),
+    )
+    .unwrap();
+    gas.carrier = "gas".to_string();
+    entities.push(Entity::Grid(gas));
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![-1.0],
+            vec![100.0],
+            vec![5.0],
+            vec![100.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    // 10 units of gas yield 4 units of electricity and 5 units of heat
+    entities.push(Entity::Converter(
+        Converter::new(
+            vec![0.0],
+            vec![10.0],
+            vec![ConverterPort::new("gas".to_string(), vec![1.0]).unwrap()],
+            vec![
+                ConverterPort::new("electricity".to_string(), vec![0.4]).unwrap(),
+                ConverterPort::new("heat".to_string(), vec![0.5]).unwrap(),
+            ],
+            "chp".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let mut consumer =
+        Consumer::new(vec![0.0], vec![1.0], vec![2.5], "building".to_string()).unwrap();
+    consumer.carrier = "heat".to_string();
+    entities.push(Entity::Consumer(consumer));
+
+    let result = solve(&entities, timesteps);
+    assert!(result.is_ok());
+
+    let mut unwrapped_result = result.unwrap();
+
+    unwrapped_result.pop();
+    let chp = match unwrapped_result.pop().unwrap() {
+        EntityResult::Converter(converter) => converter,
+        _ => panic!("Expected Converter"),
+    };
+    let grid = match unwrapped_result.pop().unwrap() {
+        EntityResult::Grid(grid) => grid,
+        _ => panic!("Expected Grid"),
+    };
+
+    assert_eq!(chp.consumed["gas"], vec![5.0]);
+    assert_eq!(chp.produced["electricity"], vec![2.0]);
+    assert_eq!(grid.consumed, vec![2.0]);
+}
+
+#[test]
+fn invest_in_pv_and_battery() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 4;
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![0.0],
+            vec![10.0],
+            vec![10.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    // 1 unit of output per unit of capacity during the day
+    let mut pv = Producer::new(
+        vec![0.0],
+        vec![1.0],
+        vec![1.0, 1.0, 0.0, 0.0],
+        true,
+        "pv".to_string(),
+    )
+    .unwrap();
+    pv.invest_power = Some(Investment::new(1.0, 0.0, 100.0).unwrap());
+    entities.push(Entity::Producer(pv));
+
+    let mut battery = Storage::new(
+        vec![0.0],
+        vec![1.0],
+        vec![10.0],
+        vec![0.0],
+        vec![1.0],
+        vec![10.0],
+        100.0,
+        0.0,
+        None,
+        false,
+        false,
+        "battery".to_string(),
+    )
+    .unwrap();
+    let mut investment = Investment::new(1.0, 0.0, 100.0).unwrap();
+    investment.module_size = Some(3.0);
+    battery.invest_capacity = Some(investment);
+    entities.push(Entity::Storage(battery));
+
+    entities.push(Entity::Consumer(
+        Consumer::new(vec![0.0], vec![1.0], vec![2.0], "consumer".to_string()).unwrap(),
+    ));
+
+    let result = solve(&entities, timesteps);
+    assert!(result.is_ok());
+
+    let mut unwrapped_result = result.unwrap();
+
+    unwrapped_result.pop();
+    let battery = match unwrapped_result.pop().unwrap() {
+        EntityResult::Storage(storage) => storage,
+        _ => panic!("Expected Storage"),
+    };
+    let pv = match unwrapped_result.pop().unwrap() {
+        EntityResult::Producer(producer) => producer,
+        _ => panic!("Expected Producer"),
+    };
+
+    // the night demand of 4 needs two battery modules
+    assert_eq!(pv.invested_capacity, Some(4.0));
+    assert_eq!(battery.invested_capacity, Some(6.0));
+    assert_eq!(battery.investment_cost, 6.0);
+}
+
+fn rolling_entities(demand: Vec<f64>) -> Vec<Entity> {
+    vec![
+        Entity::Grid(
+            Grid::new(
+                vec![0.0],
+                vec![0.0],
+                vec![1.0, 3.0],
+                vec![10.0],
+                "grid".to_string(),
+            )
+            .unwrap(),
+        ),
+        Entity::Storage(
+            Storage::new(
+                vec![0.0],
+                vec![1.0],
+                vec![1.0],
+                vec![0.0],
+                vec![1.0],
+                vec![1.0],
+                1.0,
+                0.0,
+                None,
+                false,
+                true,
+                "storage".to_string(),
+            )
+            .unwrap(),
+        ),
+        Entity::Consumer(
+            Consumer::new(vec![0.0], vec![1.0], demand, "consumer".to_string()).unwrap(),
+        ),
+    ]
+}
+
+#[test]
+fn rolling_horizon_carries_storage() {
+    let rolling = RollingHorizon {
+        timesteps: 4,
+        look_ahead: 2,
+        commit: 1,
+    };
+
+    let realized = rolling_entities(vec![1.0]);
+    let forecast = rolling_entities(vec![1.0]);
+
+    let result = solve_rolling(&realized, &forecast, &rolling, &SolveOptions::default());
+    assert!(result.is_ok());
+
+    let result = result.unwrap();
+
+    let grid = match &result.entities[0] {
+        EntityResult::Grid(grid) => grid,
+        _ => panic!("Expected Grid"),
+    };
+    let storage = match &result.entities[1] {
+        EntityResult::Storage(storage) => storage,
+        _ => panic!("Expected Storage"),
+    };
+
+    assert_float_eq!(grid.produced, vec![2.0, 0.0, 2.0, 0.0], abs_all <= 1e-6);
+    assert_float_eq!(storage.stored, vec![1.0, 0.0, 1.0, 0.0], abs_all <= 1e-6);
+    assert_float_eq!(result.cost, vec![2.0, 0.0, 2.0, 0.0], abs_all <= 1e-6);
+}
+
+#[test]
+fn rolling_horizon_with_forecast_error() {
+    let rolling = RollingHorizon {
+        timesteps: 4,
+        look_ahead: 2,
+        commit: 1,
+    };
+
+    // the controller does not expect any demand and never charges the storage
+    let realized = rolling_entities(vec![1.0]);
+    let forecast = rolling_entities(vec![0.0]);
+
+    let result = solve_rolling(&realized, &forecast, &rolling, &SolveOptions::default()).unwrap();
+
+    let consumer = match &result.entities[2] {
+        EntityResult::Consumer(consumer) => consumer,
+        _ => panic!("Expected Consumer"),
+    };
+
+    assert_float_eq!(consumer.consumed, vec![1.0, 1.0, 1.0, 1.0], abs_all <= 1e-6);
+    assert_float_eq!(result.cost, vec![1.0, 3.0, 1.0, 3.0], abs_all <= 1e-6);
+}
+
+#[test]
+fn rolling_horizon_peak_charge_counted_once() {
+    let rolling = RollingHorizon {
+        timesteps: 3,
+        look_ahead: 2,
+        commit: 1,
+    };
+
+    let mut grid = Grid::new(
+        vec![0.0],
+        vec![0.0],
+        vec![1.0],
+        vec![10.0],
+        "grid".to_string(),
+    )
+    .unwrap();
+    grid.peak_cost = 1.0;
+    let entities = vec![
+        Entity::Grid(grid),
+        Entity::Consumer(
+            Consumer::new(vec![0.0], vec![1.0], vec![1.0], "consumer".to_string()).unwrap(),
+        ),
+    ];
+
+    let result = solve_rolling(&entities, &entities, &rolling, &SolveOptions::default()).unwrap();
+
+    let grid = match &result.entities[0] {
+        EntityResult::Grid(grid) => grid,
+        _ => panic!("Expected Grid"),
+    };
+
+    // every window charges its own peak, the horizon is charged once
+    assert_float_eq!(grid.peak_charge, 1.0, abs <= 1e-6);
+    assert_float_eq!(result.cost.iter().sum::<f64>(), 4.0, abs <= 1e-6);
+}
+
+#[test]
+fn rolling_horizon_peak_window_across_solves() {
+    let rolling = RollingHorizon {
+        timesteps: 6,
+        look_ahead: 2,
+        commit: 2,
+    };
+
+    let mut grid = Grid::new(
+        vec![0.0],
+        vec![0.0],
+        vec![1.0],
+        vec![10.0],
+        "grid".to_string(),
+    )
+    .unwrap();
+    grid.peak_cost = 2.0;
+    grid.peak_window = Some(3);
+    let entities = vec![
+        Entity::Grid(grid),
+        Entity::Producer(
+            Producer::new(
+                vec![10.0, 2.5],
+                vec![1.0],
+                vec![10.0],
+                true,
+                "diesel".to_string(),
+            )
+            .unwrap(),
+        ),
+        Entity::Consumer(
+            Consumer::new(
+                vec![0.0],
+                vec![1.0],
+                vec![2.0, 0.0, 2.0, 1.0, 0.0, 0.0],
+                "consumer".to_string(),
+            )
+            .unwrap(),
+        ),
+    ];
+
+    let result = solve_rolling(&entities, &entities, &rolling, &SolveOptions::default()).unwrap();
+
+    let grid = match &result.entities[0] {
+        EntityResult::Grid(grid) => grid,
+        _ => panic!("Expected Grid"),
+    };
+
+    // The second solve starts within the first billing period, whose peak of
+    // 2 is already paid for, and ends in the second one, which diesel serves
+    assert_float_eq!(
+        grid.produced,
+        vec![2.0, 0.0, 2.0, 0.0, 0.0, 0.0],
+        abs_all <= 1e-6
+    );
+    assert_float_eq!(grid.peak, vec![2.0, 0.0], abs_all <= 1e-6);
+}
+
+#[test]
+fn rolling_horizon_carries_ramp_limits() {
+    let rolling = RollingHorizon {
+        timesteps: 4,
+        look_ahead: 2,
+        commit: 1,
+    };
+
+    let mut producer = Producer::new(
+        vec![0.0],
+        vec![1.0],
+        vec![10.0],
+        true,
+        "producer".to_string(),
+    )
+    .unwrap();
+    producer.ramp_up = vec![1.0].into();
+    let entities = vec![
+        Entity::Grid(
+            Grid::new(
+                vec![0.0],
+                vec![0.0],
+                vec![10.0],
+                vec![10.0],
+                "grid".to_string(),
+            )
+            .unwrap(),
+        ),
+        Entity::Producer(producer),
+        Entity::Consumer(
+            Consumer::new(
+                vec![0.0],
+                vec![1.0],
+                vec![0.0, 3.0, 3.0, 3.0],
+                "consumer".to_string(),
+            )
+            .unwrap(),
+        ),
+    ];
+
+    let result = solve_rolling(&entities, &entities, &rolling, &SolveOptions::default()).unwrap();
+
+    let producer = match &result.entities[1] {
+        EntityResult::Producer(producer) => producer,
+        _ => panic!("Expected Producer"),
+    };
+
+    // the ramp limit of each solve starts from the output committed before it
+    assert_float_eq!(producer.produced, vec![0.0, 1.0, 2.0, 3.0], abs_all <= 1e-6);
+}
+
+#[test]
+fn rolling_horizon_emission_cap() {
+    let rolling = RollingHorizon {
+        timesteps: 4,
+        look_ahead: 2,
+        commit: 1,
+    };
+
+    let mut coal =
+        Producer::new(vec![1.0], vec![1.0], vec![1.0], true, "coal".to_string()).unwrap();
+    coal.emission_factor = vec![1.0].into();
+    let entities = vec![
+        Entity::Grid(
+            Grid::new(
+                vec![0.0],
+                vec![0.0],
+                vec![5.0],
+                vec![10.0],
+                "grid".to_string(),
+            )
+            .unwrap(),
+        ),
+        Entity::Producer(coal),
+        Entity::Consumer(
+            Consumer::new(vec![0.0], vec![1.0], vec![1.0], "consumer".to_string()).unwrap(),
+        ),
+    ];
+    let options = SolveOptions {
+        emission_cap: Some(2.0),
+        ..Default::default()
+    };
+
+    let result = solve_rolling(&entities, &entities, &rolling, &options).unwrap();
+
+    // the cap holds for the whole horizon, not for every solve
+    assert_float_eq!(result.emissions, 2.0, abs <= 1e-6);
+}
+
+#[test]
+fn rolling_horizon_flexible_window_beyond_look_ahead() {
+    let rolling = RollingHorizon {
+        timesteps: 8,
+        look_ahead: 2,
+        commit: 1,
+    };
+
+    let mut consumer =
+        Consumer::new(vec![0.0], vec![1.0], vec![1.0], "consumer".to_string()).unwrap();
+    consumer.energy = Some(6.0);
+    let entities = vec![
+        Entity::Grid(
+            Grid::new(
+                vec![0.0],
+                vec![0.0],
+                vec![1.0, 2.0],
+                vec![10.0],
+                "grid".to_string(),
+            )
+            .unwrap(),
+        ),
+        Entity::Consumer(consumer),
+    ];
+
+    let result = solve_rolling(&entities, &entities, &rolling, &SolveOptions::default()).unwrap();
+
+    let consumer = match &result.entities[1] {
+        EntityResult::Consumer(consumer) => consumer,
+        _ => panic!("Expected Consumer"),
+    };
+
+    // each solve only takes what the timesteps after its look-ahead cannot
+    assert_float_eq!(consumer.consumed.iter().sum::<f64>(), 6.0, abs <= 1e-6);
+    assert!(consumer
+        .consumed
+        .iter()
+        .all(|consumed| *consumed <= 1.0 + 1e-6));
+}
+
+#[test]
+fn rolling_horizon_flexible_energy_in_half_hours() {
+    let rolling = RollingHorizon {
+        timesteps: 4,
+        look_ahead: 2,
+        commit: 1,
+    };
+
+    let mut consumer =
+        Consumer::new(vec![0.0], vec![1.0], vec![2.0], "consumer".to_string()).unwrap();
+    consumer.energy = Some(2.0);
+    let entities = vec![
+        Entity::Grid(
+            Grid::new(
+                vec![0.0],
+                vec![0.0],
+                vec![1.0, 2.0],
+                vec![10.0],
+                "grid".to_string(),
+            )
+            .unwrap(),
+        ),
+        Entity::Consumer(consumer),
+    ];
+    let options = SolveOptions {
+        resolution: Some(0.5),
+        ..Default::default()
+    };
+
+    let result = solve_rolling(&entities, &entities, &rolling, &options).unwrap();
+
+    let consumer = match &result.entities[1] {
+        EntityResult::Consumer(consumer) => consumer,
+        _ => panic!("Expected Consumer"),
+    };
+
+    // 2 kWh at half-hourly timesteps are 4 kW summed over the timesteps
+    assert_float_eq!(consumer.consumed.iter().sum::<f64>(), 4.0, abs <= 1e-6);
+}
+
+fn rolling_departure(look_ahead: usize) -> ElectricVehicleResult {
+    let rolling = RollingHorizon {
+        timesteps: 4,
+        look_ahead,
+        commit: 2,
+    };
+
+    let mut electric_vehicle = ElectricVehicle::new(
+        vec![2.0],
+        vec![1.0],
+        10.0,
+        0.0,
+        vec![true, true, false, false],
+        vec![0.0],
+        vec![4.0],
+        "car".to_string(),
+    )
+    .unwrap();
+    electric_vehicle.shortfall_cost = Some(100.0);
+    let entities = vec![
+        Entity::Grid(
+            Grid::new(
+                vec![0.0],
+                vec![0.0],
+                vec![1.0],
+                vec![10.0],
+                "grid".to_string(),
+            )
+            .unwrap(),
+        ),
+        Entity::ElectricVehicle(electric_vehicle),
+    ];
+
+    let result = solve_rolling(&entities, &entities, &rolling, &SolveOptions::default()).unwrap();
+    match &result.entities[1] {
+        EntityResult::ElectricVehicle(electric_vehicle) => electric_vehicle.clone(),
+        _ => panic!("Expected ElectricVehicle"),
+    }
+}
+
+#[test]
+fn rolling_horizon_departure_at_window_start() {
+    // the first solve sees the departure and charges for it
+    let electric_vehicle = rolling_departure(3);
+    assert_float_eq!(
+        electric_vehicle.stored,
+        vec![2.0, 4.0, 4.0, 4.0],
+        abs_all <= 1e-6
+    );
+    assert_float_eq!(
+        electric_vehicle.shortfall,
+        vec![0.0, 0.0, 0.0, 0.0],
+        abs_all <= 1e-6
+    );
+
+    // the first solve ends before the departure, the second one still
+    // enforces the target
+    let electric_vehicle = rolling_departure(2);
+    assert_float_eq!(
+        electric_vehicle.stored,
+        vec![0.0, 0.0, 0.0, 0.0],
+        abs_all <= 1e-6
+    );
+    assert_float_eq!(
+        electric_vehicle.shortfall,
+        vec![0.0, 0.0, 4.0, 0.0],
+        abs_all <= 1e-6
+    );
+}
+
+fn stochastic_entities(demand: f64) -> Vec<Entity> {
+    vec![
+        Entity::Grid(
+            Grid::new(
+                vec![0.0],
+                vec![0.0],
+                vec![1.0],
+                vec![10.0],
+                "day-ahead".to_string(),
+            )
+            .unwrap(),
+        ),
+        Entity::Grid(
+            Grid::new(
+                vec![0.0],
+                vec![10.0],
+                vec![4.0],
+                vec![10.0],
+                "balancing".to_string(),
+            )
+            .unwrap(),
+        ),
+        Entity::Consumer(
+            Consumer::new(vec![0.0], vec![1.0], vec![demand], "consumer".to_string()).unwrap(),
+        ),
+    ]
+}
+
+#[test]
+fn stochastic_day_ahead_purchase() {
+    let scenarios = vec![
+        StochasticScenario {
+            name: "low".to_string(),
+            weight: 2.0,
+            entities: stochastic_entities(4.0),
+        },
+        StochasticScenario {
+            name: "high".to_string(),
+            weight: 2.0,
+            entities: stochastic_entities(8.0),
+        },
+    ];
+
+    let result = solve_stochastic(
+        &scenarios,
+        &["day-ahead".to_string()],
+        1,
+        &SolveOptions::default(),
+    );
+    assert!(result.is_ok());
+
+    let result = result.unwrap();
+
+    for scenario in result.scenarios.iter() {
+        assert_eq!(scenario.weight, 0.5);
+
+        let day_ahead = match &scenario.result.entities[0] {
+            EntityResult::Grid(grid) => grid,
+            _ => panic!("Expected Grid"),
+        };
+        assert_eq!(day_ahead.produced, vec![8.0]);
+    }
+
+    let balancing = match &result.scenarios[0].result.entities[1] {
+        EntityResult::Grid(grid) => grid,
+        _ => panic!("Expected Grid"),
+    };
+    assert_eq!(balancing.consumed, vec![4.0]);
+    assert_eq!(result.expected_cost, 8.0);
+}
+
+#[test]
+fn stochastic_purchase_with_different_power() {
+    let mut high = stochastic_entities(8.0);
+    if let Entity::Grid(grid) = &mut high[0] {
+        grid.power_prod = vec![20.0].into();
+    }
+    let scenarios = vec![
+        StochasticScenario {
+            name: "low".to_string(),
+            weight: 1.0,
+            entities: stochastic_entities(4.0),
+        },
+        StochasticScenario {
+            name: "high".to_string(),
+            weight: 1.0,
+            entities: high,
+        },
+    ];
+
+    let result = solve_stochastic(
+        &scenarios,
+        &["day-ahead".to_string()],
+        1,
+        &SolveOptions::default(),
+    )
+    .unwrap();
+
+    // The purchase is the same power, not the same fraction of the power
+    for scenario in result.scenarios.iter() {
+        let day_ahead = match &scenario.result.entities[0] {
+            EntityResult::Grid(grid) => grid,
+            _ => panic!("Expected Grid"),
+        };
+        assert_float_eq!(day_ahead.produced[0], 8.0, abs <= 1e-6);
+    }
+}
+
+#[test]
+fn stochastic_different_first_stage_decisions() {
+    let mut high = stochastic_entities(8.0);
+    if let Entity::Grid(grid) = &mut high[0] {
+        grid.peak_cost = 1.0;
+    }
+    let scenarios = vec![
+        StochasticScenario {
+            name: "low".to_string(),
+            weight: 1.0,
+            entities: stochastic_entities(4.0),
+        },
+        StochasticScenario {
+            name: "high".to_string(),
+            weight: 1.0,
+            entities: high,
+        },
+    ];
+
+    let result = solve_stochastic(
+        &scenarios,
+        &["day-ahead".to_string()],
+        1,
+        &SolveOptions::default(),
+    );
+    assert!(matches!(result, Err(Error::Invalid { .. })));
+}
+
+#[test]
+fn diagnose_infeasible_problem() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 2;
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![0.0],
+            vec![1.0],
+            vec![4.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let mut depot_grid = Grid::new(
+        vec![0.0],
+        vec![0.0],
+        vec![1.0],
+        vec![4.0],
+        "depot grid".to_string(),
+    )
+    .unwrap();
+    depot_grid.bus = "depot".to_string();
+    entities.push(Entity::Grid(depot_grid));
+
+    let mut storage = Storage::new(
+        vec![0.0],
+        vec![1.0],
+        vec![1.0],
+        vec![0.0],
+        vec![1.0],
+        vec![1.0],
+        10.0,
+        0.0,
+        Some(5.0),
+        false,
+        true,
+        "storage".to_string(),
+    )
+    .unwrap();
+    storage.bus = "depot".to_string();
+    entities.push(Entity::Storage(storage));
+
+    entities.push(Entity::Consumer(
+        Consumer::new(vec![0.0], vec![1.0], vec![5.0, 1.0], "consumer".to_string()).unwrap(),
+    ));
+
+    assert!(solve(&entities, timesteps).is_err());
+
+    let relaxations = diagnose(&entities, timesteps, &SolveOptions::default());
+    assert!(relaxations.is_ok());
+
+    let relaxations = relaxations.unwrap();
+    assert_eq!(relaxations.len(), 2);
+
+    assert_eq!(relaxations[0].constraint, "Kirchhoff");
+    assert_eq!(relaxations[0].entity, "main");
+    assert_eq!(relaxations[0].timestep, 0);
+    assert_float_eq!(relaxations[0].violation, -1.0, abs <= 1e-6);
+
+    assert_eq!(relaxations[1].constraint, "End capacity");
+    assert_eq!(relaxations[1].entity, "storage");
+    assert_eq!(relaxations[1].timestep, 1);
+    assert_float_eq!(relaxations[1].violation, -3.0, abs <= 1e-6);
+}
+
+#[test]
+fn diagnose_in_quarter_hours() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 1;
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![0.0],
+            vec![1.0],
+            vec![4.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    entities.push(Entity::Consumer(
+        Consumer::new(vec![0.0], vec![1.0], vec![5.0], "consumer".to_string()).unwrap(),
+    ));
+
+    let options = SolveOptions {
+        resolution: Some(0.25),
+        ..Default::default()
+    };
+    let relaxations = diagnose(&entities, timesteps, &options).unwrap();
+    assert_eq!(relaxations.len(), 1);
+
+    // 1 kW missing for a quarter of an hour
+    assert_eq!(relaxations[0].constraint, "Kirchhoff");
+    assert_float_eq!(relaxations[0].violation, -0.25, abs <= 1e-6);
+}
+
+#[test]
+fn diagnose_drained_storage() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 2;
+
+    // The vehicle is away for the whole horizon and the trip needs more energy
+    // than is left in the battery
+    entities.push(Entity::ElectricVehicle(
+        ElectricVehicle::new(
+            vec![10.0],
+            vec![1.0],
+            40.0,
+            2.0,
+            vec![false, false],
+            vec![0.0, 5.0],
+            vec![0.0],
+            "car".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    assert!(solve(&entities, timesteps).is_err());
+
+    let relaxations = diagnose(&entities, timesteps, &SolveOptions::default()).unwrap();
+    assert_eq!(relaxations.len(), 1);
+
+    assert_eq!(relaxations[0].constraint, "Storage empty");
+    assert_eq!(relaxations[0].entity, "car");
+    assert_eq!(relaxations[0].timestep, 1);
+    assert_float_eq!(relaxations[0].violation, 3.0, abs <= 1e-6);
+}
+
+#[test]
+fn backend_without_integer_support() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 1;
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![0.0],
+            vec![1.0],
+            vec![10.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let options = SolveOptions {
+        backend: SolverBackend::Clarabel,
+        ..Default::default()
+    };
+
+    let result = solve_with_options(&entities, timesteps, &options);
+    assert!(result.is_err());
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .contains("binary or integer variables"));
+}
+
+#[test]
+fn solver_options() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 2;
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![0.0],
+            vec![1.0],
+            vec![10.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    entities.push(Entity::Consumer(
+        Consumer::new(vec![0.0], vec![1.0], vec![2.0], "consumer".to_string()).unwrap(),
+    ));
+
+    let options = SolveOptions {
+        time_limit: Some(60.0),
+        mip_rel_gap: Some(0.0),
+        threads: Some(1),
+        presolve: Some(false),
+        ..Default::default()
+    };
+
+    let result = solve_with_options(&entities, timesteps, &options);
+    assert!(result.is_ok());
+
+    let result = result.unwrap();
+    assert_eq!(result.status, SolveStatus::Optimal);
+    assert_eq!(result.gap, None);
+    assert_eq!(result.cost, vec![2.0, 2.0]);
+
+    let options = SolveOptions {
+        time_limit: Some(0.0),
+        ..Default::default()
+    };
+    assert!(solve_with_options(&entities, timesteps, &options).is_err());
+}
+
+#[test]
+fn invalid_input() {
+    let result = Consumer::new(vec![0.0], vec![1.0], vec![], "consumer".to_string());
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "power_cons of consumer must not be empty"
+    );
+
+    let result = Storage::new(
+        vec![0.0],
+        vec![1.0],
+        vec![1.0],
+        vec![0.0],
+        vec![1.5],
+        vec![1.0],
+        20.0,
+        10.0,
+        None,
+        false,
+        false,
+        "storage".to_string(),
+    );
+    assert_eq!(
+        result.unwrap_err(),
+        Error::invalid("storage", "eff_prod", "must be between 0 and 1")
+    );
+
+    // Fields set after the construction are checked when solving
+    let mut grid = Grid::new(
+        vec![0.0],
+        vec![0.0],
+        vec![1.0],
+        vec![10.0],
+        "grid".to_string(),
+    )
+    .unwrap();
+    grid.peak_cost = 5.0;
+    grid.peak_window = Some(0);
+
+    let result = solve(&[Entity::Grid(grid)], 2);
+    match result {
+        Err(Error::Invalid { entity, field, .. }) => {
+            assert_eq!(entity, "grid");
+            assert_eq!(field, "peak_window");
+        }
+        _ => panic!("Expected an invalid peak_window"),
+    }
+}
+
+#[test]
+fn scenario_json_round_trip() {
+    let json = r#"{
+        "timesteps": 2,
+        "carbon_price": 0.5,
+        "backend": "highs",
+        "entities": [
+            { "name": "grid", "entity_type": "Grid", "cost_prod": [1.0], "power_prod": [10.0], "cost_cons": [0.0], "power_cons": [0.0] },
+            { "name": "battery", "entity_type": "Storage", "bus": "depot", "cost_prod": [0.0], "eff_prod": [1.0], "power_prod": [5.0],
+              "cost_cons": [0.0], "eff_cons": [1.0], "power_cons": [5.0], "start_capacity": 0.0,
+              "invest_capacity": { "cost": 1.0, "min": 0.0, "max": 20.0 } },
+            { "name": "cable", "entity_type": "Line", "from": "main", "to": "depot", "cost": [0.0], "eff": [1.0], "power_fwd": [5.0] }
+        ]
+    }"#;
+
+    let scenario = Scenario::from_json(json).unwrap();
+    assert_eq!(scenario.options().carbon_price, 0.5);
+    assert_eq!(scenario.options().backend, SolverBackend::Highs);
+
+    match &scenario.entities[1] {
+        Entity::Storage(storage) => {
+            assert_eq!(storage.bus, "depot");
+            assert_eq!(storage.carrier, "electricity");
+            assert_eq!(storage.storage_capacity, 20.0);
+        }
+        _ => panic!("Expected a storage"),
+    }
+    match &scenario.entities[2] {
+        Entity::Line(line) => assert_eq!(line.power_bwd, TimeSeries::from(vec![5.0])),
+        _ => panic!("Expected a line"),
+    }
+
+    let written = scenario.to_json().unwrap();
+    assert_eq!(
+        Scenario::from_json(&written).unwrap().to_json().unwrap(),
+        written
+    );
+
+    // The tag is written from the variant, also for entities built in code
+    let grid = Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![0.0],
+            vec![1.0],
+            vec![10.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    );
+    let written = serde_json::to_value(&grid).unwrap();
+    assert_eq!(written["entity_type"], "Grid");
+    assert!(matches!(
+        serde_json::from_value::<Entity>(written),
+        Ok(Entity::Grid(_))
+    ));
+
+    // Entities are validated when they are read
+    let result = Scenario::from_json(&json.replace(r#""eff": [1.0]"#, r#""eff": [2.0]"#));
+    match result {
+        Err(Error::Parse(message)) => {
+            assert!(message.contains("eff of cable must be between 0 and 1"))
+        }
+        _ => panic!("Expected an invalid eff"),
+    }
+}
+
+/// Load with a fixed power, defined outside of the crate.
+#[derive(Debug, Clone, Serialize)]
+struct FixedLoad {
+    name: String,
+    power: f64,
+}
+
+impl EnergyEntity for FixedLoad {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn add_timestep(&self, model: &mut TimestepModel, vars: &mut EntityVars) -> Result<(), Error> {
+        let consumed = model.add_variable(variable().min(0).max(self.power));
+        vars.push("consumed", consumed);
+
+        model.add_constraint(Row::eq(consumed, self.power));
+        model.add_to_node("main", "electricity", consumed * -1.0);
+        Ok(())
+    }
+
+    fn collect_results(&self, vars: &EntityVars, results: &ModelResults) -> EntityResult {
+        let consumed: Vec<f64> = vars
+            .get("consumed")
+            .iter()
+            .map(|var| results.value(*var))
+            .collect();
+        EntityResult::Custom(serde_json::json!({
+            "name": self.name,
+            "consumed": consumed,
+            "price": results.price("main", "electricity"),
+        }))
+    }
+}
+
+#[test]
+fn custom_entity() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 2;
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![10.0],
+            vec![2.0],
+            vec![10.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    entities.push(Entity::Custom(Box::new(FixedLoad {
+        name: "load".to_string(),
+        power: 3.0,
+    })));
+
+    let result = solve_with_options(
+        &entities,
+        timesteps,
+        &SolveOptions {
+            prices: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(result.cost, vec![6.0, 6.0]);
+
+    let grid = match &result.entities[0] {
+        EntityResult::Grid(grid) => grid,
+        _ => panic!("Expected Grid"),
+    };
+    assert_eq!(grid.produced, vec![3.0, 3.0]);
+
+    assert_eq!(result.entities[1].name(), "load");
+    let json = serde_json::to_value(&result.entities[1]).unwrap();
+    assert_eq!(json["entity_type"], "Custom");
+    assert_eq!(json["consumed"], serde_json::json!([3.0, 3.0]));
+    assert_eq!(json["price"], serde_json::json!([2.0, 2.0]));
+}
+
+/// Custom entity whose serializer always fails.
+#[derive(Debug, Clone)]
+struct Unwritable(FixedLoad);
+
+impl Serialize for Unwritable {
+    fn serialize<S>(&self, _serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        Err(serde::ser::Error::custom("unwritable"))
+    }
+}
+
+impl EnergyEntity for Unwritable {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn add_timestep(&self, model: &mut TimestepModel, vars: &mut EntityVars) -> Result<(), Error> {
+        self.0.add_timestep(model, vars)
+    }
+
+    fn collect_results(&self, _vars: &EntityVars, _results: &ModelResults) -> EntityResult {
+        EntityResult::Custom(serde_json::Value::Null)
+    }
+}
+
+#[test]
+fn custom_entity_serialization_error() {
+    let entity = Entity::Custom(Box::new(Unwritable(FixedLoad {
+        name: "load".to_string(),
+        power: 3.0,
+    })));
+    assert!(serde_json::to_value(&entity)
+        .unwrap_err()
+        .to_string()
+        .contains("unwritable"));
+
+    let result = EntityResult::Custom(serde_json::Value::Null);
+    assert!(serde_json::to_value(&result).is_err());
+}
+
+#[test]
+fn solve_same_model_twice() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 2;
+
+    let mut grid = Grid::new(
+        vec![0.0],
+        vec![10.0],
+        vec![1.0, 3.0],
+        vec![10.0],
+        "grid".to_string(),
+    )
+    .unwrap();
+    grid.emission_factor = vec![0.5].into();
+    entities.push(Entity::Grid(grid));
+
+    entities.push(Entity::Consumer(
+        Consumer::new(vec![0.0], vec![1.0], vec![4.0], "consumer".to_string()).unwrap(),
+    ));
+    let input = serde_json::to_value(&entities).unwrap();
+
+    let first = solve_with_options(&entities, timesteps, &SolveOptions::default()).unwrap();
+    assert_eq!(first.objective, 16.0);
+    assert_eq!(first.status, SolveStatus::Optimal);
+
+    // Solving again with a carbon price does not depend on the first solve
+    // and leaves the entities unchanged
+    let options = SolveOptions {
+        carbon_price: 1.0,
+        ..Default::default()
+    };
+    let second = solve_with_options(&entities, timesteps, &options).unwrap();
+    assert_eq!(second.objective, 20.0);
+    assert_eq!(serde_json::to_value(&entities).unwrap(), input);
+
+    match (&first.entities[1], &second.entities[1]) {
+        (EntityResult::Consumer(first), EntityResult::Consumer(second)) => {
+            assert_eq!(first.consumed, vec![4.0, 4.0]);
+            assert_eq!(second.consumed, first.consumed);
+        }
+        _ => panic!("Expected Consumer"),
+    }
+}
+
+#[test]
+fn hourly_profile_in_quarter_hours() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 8;
+
+    entities.push(Entity::Grid(
+        Grid::new(
+            vec![0.0],
+            vec![0.0],
+            vec![1.0],
+            vec![10.0],
+            "grid".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let mut consumer =
+        Consumer::new(vec![0.0], vec![1.0], vec![0.0], "consumer".to_string()).unwrap();
+    consumer.power_cons = TimeSeries::Periodic {
+        values: vec![1.0, 3.0],
+        resolution: Some(1.0),
+    };
+    entities.push(Entity::Consumer(consumer));
+
+    let mut ramp = Consumer::new(vec![0.0], vec![1.0], vec![0.0], "ramp".to_string()).unwrap();
+    ramp.power_cons = TimeSeries::PiecewiseLinear {
+        points: vec![(0.0, 0.0), (1.0, 4.0)],
+    };
+    entities.push(Entity::Consumer(ramp));
+
+    let options = SolveOptions {
+        resolution: Some(0.25),
+        ..Default::default()
+    };
+    let result = solve_with_options(&entities, timesteps, &options).unwrap();
+
+    match &result.entities[1] {
+        EntityResult::Consumer(consumer) => {
+            assert_eq!(
+                consumer.consumed,
+                vec![1.0, 1.0, 1.0, 1.0, 3.0, 3.0, 3.0, 3.0]
+            );
+        }
+        _ => panic!("Expected Consumer"),
+    }
+    // The mean of the interpolation over every quarter hour
+    match &result.entities[2] {
+        EntityResult::Consumer(ramp) => {
+            assert_eq!(ramp.consumed, vec![0.5, 1.5, 2.5, 3.5, 4.0, 4.0, 4.0, 4.0]);
+        }
+        _ => panic!("Expected Consumer"),
+    }
+}
+
+#[test]
+fn storage_in_quarter_hours() {
+    let mut entities: Vec<Entity> = vec![];
+    let timesteps = 4;
+
+    let mut grid = Grid::new(
+        vec![0.0],
+        vec![0.0],
+        vec![1.0, 1.0, 3.0, 3.0],
+        vec![10.0],
+        "grid".to_string(),
+    )
+    .unwrap();
+    grid.emission_factor = vec![0.5].into();
+    entities.push(Entity::Grid(grid));
+
+    entities.push(Entity::Storage(
+        Storage::new(
+            vec![0.0],
+            vec![1.0],
+            vec![4.0],
+            vec![0.0],
+            vec![1.0],
+            vec![4.0],
+            10.0,
+            0.0,
+            None,
+            false,
+            true,
+            "storage".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    entities.push(Entity::Consumer(
+        Consumer::new(
+            vec![0.0],
+            vec![1.0],
+            vec![0.0, 0.0, 4.0, 4.0],
+            "consumer".to_string(),
+        )
+        .unwrap(),
+    ));
+
+    let options = SolveOptions {
+        resolution: Some(0.25),
+        ..Default::default()
+    };
+    let result = solve_with_options(&entities, timesteps, &options).unwrap();
+
+    // 4 kW for a quarter hour store 1 kWh
+    match &result.entities[1] {
+        EntityResult::Storage(storage) => {
+            assert_eq!(storage.consumed, vec![4.0, 4.0, 0.0, 0.0]);
+            assert_eq!(storage.produced, vec![0.0, 0.0, 4.0, 4.0]);
+            assert_float_eq!(storage.stored, vec![1.0, 2.0, 1.0, 0.0], abs_all <= 1e-9);
+        }
+        _ => panic!("Expected Storage"),
+    }
+    match &result.entities[0] {
+        EntityResult::Grid(grid) => {
+            assert_float_eq!(grid.energy_cost, 2.0, abs <= 1e-9);
+            assert_float_eq!(grid.emissions, vec![0.5, 0.5, 0.0, 0.0], abs_all <= 1e-9);
+        }
+        _ => panic!("Expected Grid"),
+    }
+    assert_float_eq!(result.objective, 2.0, abs <= 1e-9);
+    assert_float_eq!(result.emissions, 1.0, abs <= 1e-9);
+}
+
+#[test]
+fn time_series_json() {
+    let json = r#"{
+        "timesteps": 4,
+        "resolution": 0.5,
+        "entities": [
+            { "name": "grid", "entity_type": "Grid", "cost_prod": 1.0, "power_prod": [10.0], "cost_cons": [0.0], "power_cons": [0.0] },
+            { "name": "consumer", "entity_type": "Consumer", "cost_cons": [0.0], "eff_cons": [1.0],
+              "power_cons": { "type": "explicit", "values": [2.0, 1.0], "resolution": 1.0 } }
+        ]
+    }"#;
+
+    let scenario = Scenario::from_json(json).unwrap();
+    let result =
+        solve_with_options(&scenario.entities, scenario.timesteps, &scenario.options()).unwrap();
+    match &result.entities[1] {
+        EntityResult::Consumer(consumer) => assert_eq!(consumer.consumed, vec![2.0, 2.0, 1.0, 1.0]),
+        _ => panic!("Expected Consumer"),
+    }
+
+    // Plain arrays and numbers are written back as they were read
+    let written: serde_json::Value = serde_json::from_str(&scenario.to_json().unwrap()).unwrap();
+    assert_eq!(written["entities"][0]["cost_prod"], serde_json::json!(1.0));
+    assert_eq!(
+        written["entities"][0]["power_prod"],
+        serde_json::json!([10.0])
+    );
+    assert_eq!(written["entities"][1]["power_cons"]["type"], "explicit");
+
+    let result = Scenario::from_json(&json.replace(r#""resolution": 1.0"#, r#""resolution": 0.0"#));
+    match result {
+        Err(Error::Parse(message)) => {
+            assert!(message.contains("power_cons of consumer must have a positive resolution"))
+        }
+        _ => panic!("Expected an invalid resolution"),
+    }
+}
+
+#[test]
+fn empty_time_series() {
+    assert_eq!(TimeSeries::default().get(3), 0.0);
+    assert_eq!(
+        TimeSeries::Explicit {
+            values: vec![],
+            resolution: None
+        }
+        .get(3),
+        0.0
+    );
+    assert_eq!(
+        TimeSeries::Periodic {
+            values: vec![],
+            resolution: Some(1.0)
+        }
+        .value(3, 0.25),
+        0.0
+    );
+    assert_eq!(
+        TimeSeries::PiecewiseLinear { points: vec![] }.value(3, 0.25),
+        0.0
+    );
+}